@@ -0,0 +1,44 @@
+//! Lexer and parser throughput on documents of growing size
+//!
+//! Run with `cargo bench`. The time per byte should stay roughly flat as the input grows.
+
+use md_parser::{Lexer, Parser};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const SAMPLE: &str = include_str!("../test.md");
+const SIZES: [usize; 4] = [1, 4, 16, 64];
+const MIN_DURATION: Duration = Duration::from_millis(200);
+
+fn bench<T>(name: &str, bytes: usize, mut f: impl FnMut() -> T) {
+    black_box(f());
+
+    let mut iterations: u32 = 0;
+    let start = Instant::now();
+    while start.elapsed() < MIN_DURATION {
+        black_box(f());
+        iterations += 1;
+    }
+    let per_iteration = start.elapsed() / iterations;
+
+    println!(
+        "{name:<8} {bytes:>9} B {:>12.3?} {:>8.2} ns/B",
+        per_iteration,
+        per_iteration.as_nanos() as f64 / bytes as f64
+    );
+}
+
+fn main() {
+    for size in SIZES {
+        let input = SAMPLE.repeat(size);
+        bench("lex", input.len(), || Lexer::new(&input).tokenize());
+    }
+
+    for size in SIZES {
+        let input = SAMPLE.repeat(size);
+        let (tokens, spans) = Lexer::new(&input).tokenize_with_spans();
+        bench("parse", input.len(), || {
+            Parser::document(&tokens, &spans).parse(false)
+        });
+    }
+}
@@ -0,0 +1,627 @@
+use crate::diagnostics::Diagnostic;
+use crate::document::Document;
+use crate::headings;
+use crate::html::{self, ConcealMode, Options, SolutionMode};
+use crate::lexer::{HrStyle, Span};
+use crate::parser::{Label, ListType, Node};
+use crate::references::Target;
+use crate::render::{self, environment_id, is_concealed, Renderer};
+use crate::toc::{TocEntry, TocKind};
+
+/// The CommonMark backend, with the GitHub extensions for strikethrough and math
+///
+/// What standard Markdown cannot express falls back to inline HTML or to a plainer form:
+/// underline and highlight become `<u>` and `<mark>`, environments blockquotes with a bold title,
+/// folded ones `<details>`, and every rule `---`. Each of these is reported in `diagnostics`
+/// where it happens. Sidenotes have no fallback: the parser has no node for them yet, so `{{…}}`
+/// reaches this backend as plain text.
+#[derive(Debug, Clone, Default)]
+pub struct CommonMarkRenderer {
+    options: Options,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl CommonMarkRenderer {
+    pub fn new(options: Options) -> Self {
+        Self {
+            options,
+            diagnostics: vec![],
+        }
+    }
+
+    /// Conversions that lost something so far
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn into_diagnostics(self) -> Vec<Diagnostic> {
+        self.diagnostics
+    }
+
+    fn lang(&self) -> &str {
+        self.options.lang.as_deref().unwrap_or("en")
+    }
+
+    /// Reports a lossy conversion at the `span` it happens at
+    fn lossy(&mut self, message: String, span: Span) {
+        self.diagnostics.push(Diagnostic::warning(message, span));
+    }
+
+    /// Renders an environment, citing the statement it is attached to when `link_statement` is
+    /// set or when it is given with `of`
+    fn environment_block(&mut self, node: &Node, link_statement: bool) -> String {
+        let Node::Env {
+            environment_type,
+            span,
+            environment_arg,
+            params,
+            attached,
+            number,
+            children,
+            ..
+        } = node
+        else {
+            return self.node(node);
+        };
+        let concealed = environment_type.conceal && is_concealed(params, &self.options);
+        if concealed && self.options.conceal == ConcealMode::Strip {
+            return String::new();
+        }
+        let children = match &environment_type.qed {
+            Some(mark) => self.nodes_with_qed(children, mark),
+            None => self.nodes(children),
+        };
+
+        let hidden = concealed && self.options.conceal == ConcealMode::Hide;
+        // Folded environments get an HTML title, in which Markdown is not read
+        let folded = environment_type.is_folded(params) || hidden;
+
+        let mut details = vec![];
+        if let Some(statement) = attached
+            .as_ref()
+            .filter(|_| link_statement || params.text("of").is_some())
+        {
+            let name = statement_name(statement, self.lang());
+            details.push(if folded {
+                format!(
+                    "<a href=\"#{}\">{}</a>",
+                    html::escape_attribute(&statement.id),
+                    html::escape_text(&name)
+                )
+            } else {
+                format!("[{}](#{})", escape(&name), statement.id)
+            });
+        }
+        if let Some(arg) = environment_arg {
+            details.push(self.nodes(arg));
+        }
+        let details = details.join(", ");
+        let label = environment_type.label_for(self.lang()).map(|label| {
+            if number.is_empty() {
+                label.to_string()
+            } else {
+                format!("{label} {}", headings::format_number(number))
+            }
+        });
+        let anchor = environment_id(node)
+            .map(|id| anchor(&id))
+            .unwrap_or_default();
+
+        if folded {
+            self.lossy(
+                "folded and concealed environments are written as HTML <details>".into(),
+                *span,
+            );
+            let open = if params.flag("open") == Some(true) && !hidden {
+                " open"
+            } else {
+                ""
+            };
+            let title = match (label, details.is_empty()) {
+                (Some(label), true) => format!("<b>{}</b>", html::escape_text(&label)),
+                (Some(label), false) => format!("<b>{}</b> ({details})", html::escape_text(&label)),
+                (None, _) => details,
+            };
+            let summary = if title.is_empty() {
+                String::new()
+            } else {
+                format!("<summary>{anchor}{title}</summary>\n")
+            };
+            let anchor = if title.is_empty() && !anchor.is_empty() {
+                format!("{anchor}\n\n")
+            } else {
+                String::new()
+            };
+            format!(
+                "{anchor}<details{open}>\n{summary}\n{}\n\n</details>\n\n",
+                children.trim_end()
+            )
+        } else {
+            let title = match (label, details.is_empty()) {
+                (Some(label), true) => format!("**{}**", escape(&label)),
+                (Some(label), false) => format!("**{}** ({details})", escape(&label)),
+                (None, _) => details,
+            };
+            if title.is_empty() {
+                return children;
+            }
+            self.lossy("environments are written as blockquotes".into(), *span);
+            quote(&format!("{anchor}{title}\n\n{}", children.trim_end()))
+        }
+    }
+
+    /// `children` between `delimiter`s, which only count as such next to something other than
+    /// whitespace
+    fn delimited(&mut self, delimiter: &str, children: &[Node]) -> String {
+        let inner = self.nodes(children);
+        let content = inner.trim();
+        if content.is_empty() {
+            return inner;
+        }
+        let start = inner.len() - inner.trim_start().len();
+        let end = start + content.len();
+        format!(
+            "{}{delimiter}{content}{delimiter}{}",
+            &inner[..start],
+            &inner[end..]
+        )
+    }
+
+    fn toc_list(&mut self, entries: &[TocEntry]) -> String {
+        let mut items = String::new();
+        for entry in entries {
+            let title = match &entry.kind {
+                TocKind::Section
+                    if self
+                        .options
+                        .toc_depth
+                        .is_some_and(|depth| entry.depth() > depth) =>
+                {
+                    continue
+                }
+                TocKind::Section if self.options.number_sections => format!(
+                    "{} {}",
+                    headings::format_number(&entry.number),
+                    self.nodes(&entry.title)
+                ),
+                TocKind::Section => self.nodes(&entry.title),
+                TocKind::Environment(_) if !self.options.toc_environments => continue,
+                TocKind::Environment(environment_type) => {
+                    let label = environment_type
+                        .label_for(self.lang())
+                        .unwrap_or(&environment_type.name);
+                    let mut title = format!(
+                        "{} {}",
+                        escape(label),
+                        headings::format_number(&entry.number)
+                    );
+                    if !entry.title.is_empty() {
+                        title.push_str(&format!(" ({})", self.nodes(&entry.title)));
+                    }
+                    title
+                }
+            };
+            items.push_str(&format!("- [{title}](#{})\n", entry.id));
+            items.push_str(&indent(&self.toc_list(&entry.children), "  "));
+        }
+        items
+    }
+}
+
+impl Renderer for CommonMarkRenderer {
+    fn options(&self) -> &Options {
+        &self.options
+    }
+
+    fn document(&mut self, document: &Document) -> String {
+        let body = match self.options.solutions {
+            SolutionMode::Collect => render::chapters(self, &document.nodes),
+            _ => self.nodes(&document.nodes),
+        };
+        let body = format!("{}\n", body.trim_end());
+        let entries = &document.metadata.entries;
+        if !self.options.standalone || entries.is_empty() {
+            return body;
+        }
+        // The YAML front matter most Markdown publishing tools read
+        let mut front_matter = String::from("---\n");
+        for (key, value) in entries {
+            front_matter.push_str(&format!("{key}: {}\n", yaml_scalar(value)));
+        }
+        format!("{front_matter}---\n\n{body}")
+    }
+
+    /// Separates blocks with a blank line and turns line breaks between inline content into
+    /// hard breaks
+    fn nodes(&mut self, nodes: &[Node]) -> String {
+        let is_inline = |node: Option<&Node>| {
+            node.is_some_and(|node| !is_block(node) && !matches!(node, Node::NewLine))
+        };
+        let mut out = String::new();
+        for (index, node) in nodes.iter().enumerate() {
+            if is_block(node) {
+                let block = self.node(node);
+                if block.is_empty() {
+                    continue;
+                }
+                if !out.is_empty() && !out.ends_with("\n\n") {
+                    out.push_str(if out.ends_with('\n') { "\n" } else { "\n\n" });
+                }
+                out.push_str(&block);
+            } else if matches!(node, Node::NewLine) {
+                let previous = index.checked_sub(1).map(|index| &nodes[index]);
+                if is_inline(previous) && is_inline(nodes.get(index + 1)) {
+                    out.push_str("\\\n");
+                } else if !out.is_empty() && !out.ends_with('\n') {
+                    out.push('\n');
+                }
+            } else {
+                let inline = self.node(node);
+                if out.is_empty() || out.ends_with('\n') {
+                    out.push_str(&escape_line_start(&inline));
+                } else {
+                    out.push_str(&inline);
+                }
+            }
+        }
+        out
+    }
+
+    fn header(&mut self, level: u8, id: Option<&str>, number: &[u32], children: &[Node]) -> String {
+        let mut title = self.nodes(children).trim().to_string();
+        if self.options.number_sections && !number.is_empty() {
+            title.insert_str(0, &format!("{} ", headings::format_number(number)));
+        }
+        if let Some(id) = id {
+            title.insert_str(0, &anchor(id));
+        }
+        format!("{} {title}\n\n", "#".repeat(level.clamp(1, 6) as usize))
+    }
+
+    fn bold(&mut self, children: &[Node]) -> String {
+        self.delimited("**", children)
+    }
+
+    fn italic(&mut self, children: &[Node]) -> String {
+        self.delimited("*", children)
+    }
+
+    fn striked(&mut self, children: &[Node]) -> String {
+        self.delimited("~~", children)
+    }
+
+    fn underline(&mut self, children: &[Node], span: Span) -> String {
+        self.lossy("underlined text is written as HTML <u>".into(), span);
+        format!("<u>{}</u>", self.nodes(children))
+    }
+
+    fn highlighted(&mut self, children: &[Node], span: Span) -> String {
+        self.lossy("highlighted text is written as HTML <mark>".into(), span);
+        format!("<mark>{}</mark>", self.nodes(children))
+    }
+
+    fn link(&mut self, url: &str, children: &[Node]) -> String {
+        if self.options.safe && !html::is_safe_url(url) {
+            return self.nodes(children);
+        }
+        let url = if url.contains([' ', '(', ')']) {
+            format!("<{url}>")
+        } else {
+            url.to_string()
+        };
+        format!("[{}]({url})", self.nodes(children))
+    }
+
+    fn list(&mut self, list_type: &ListType, children: &[Node]) -> String {
+        let marker = match list_type {
+            ListType::Normal => "- ",
+        };
+        let mut out = String::new();
+        for item in children {
+            let item = self.node(item);
+            out.push_str(marker);
+            out.push_str(indent(&item, "  ").trim_start());
+        }
+        out.push('\n');
+        out
+    }
+
+    fn list_item(&mut self, children: &[Node]) -> String {
+        format!("{}\n", self.nodes(children).trim())
+    }
+
+    fn inline_code(&mut self, code: &str) -> String {
+        let fence = "`".repeat(longest_run(code, '`') + 1);
+        if code.starts_with('`') || code.ends_with('`') {
+            format!("{fence} {code} {fence}")
+        } else {
+            format!("{fence}{code}{fence}")
+        }
+    }
+
+    fn code_block(&mut self, language: Option<&str>, code: &str) -> String {
+        let fence = "`".repeat(longest_run(code, '`').max(2) + 1);
+        let code = match language {
+            Some(language) => format!("{language}\n{code}"),
+            // Without a line break, all there is is the code
+            None if !code.contains('\n') => format!("\n{code}"),
+            None => code.to_string(),
+        };
+        let newline = if code.ends_with('\n') { "" } else { "\n" };
+        format!("{fence}{code}{newline}{fence}\n\n")
+    }
+
+    fn raw_html(&mut self, html: &str) -> String {
+        if self.options.safe {
+            return String::new();
+        }
+        format!("{}\n\n", html.trim_end())
+    }
+
+    fn inline_math(&mut self, math: &str, span: Span) -> String {
+        self.lossy(
+            "math is written between $ signs, which only some Markdown flavours render".into(),
+            span,
+        );
+        format!("${}$", math.trim())
+    }
+
+    fn display_math(
+        &mut self,
+        math: &str,
+        span: Span,
+        label: Option<&Label>,
+        number: &[u32],
+    ) -> String {
+        self.lossy(
+            "math is written between $ signs, which only some Markdown flavours render".into(),
+            span,
+        );
+        let tag = match number {
+            [] => String::new(),
+            number => format!(" \\tag{{{}}}", headings::format_number(number)),
+        };
+        let anchor = match label {
+            Some(label) => format!("{}\n\n", anchor(&label.name)),
+            None => String::new(),
+        };
+        format!("{anchor}$$\n{}{tag}\n$$\n\n", math.trim())
+    }
+
+    fn environment(&mut self, node: &Node) -> String {
+        match node {
+            Node::Env {
+                environment_type, ..
+            } if environment_type.solution && self.options.solutions != SolutionMode::Inline => {
+                match (self.options.solutions, environment_id(node)) {
+                    (SolutionMode::Collect, Some(id)) => format!(
+                        "[{}](#{id})\n\n",
+                        escape(
+                            environment_type
+                                .label_for(self.lang())
+                                .unwrap_or(&environment_type.name)
+                        )
+                    ),
+                    _ => String::new(),
+                }
+            }
+            _ => self.environment_block(node, false),
+        }
+    }
+
+    /// `mark` ends the last paragraph, or follows the last block on its own
+    fn nodes_with_qed(&mut self, nodes: &[Node], mark: &str) -> String {
+        let content = self.nodes(nodes);
+        let last = nodes.iter().rfind(|node| !matches!(node, Node::NewLine));
+        match last {
+            Some(node) if is_block(node) && !matches!(node, Node::Paragraph(_)) => {
+                format!("{}\n\n{}\n\n", content.trim_end(), escape(mark))
+            }
+            _ => format!("{} {}\n\n", content.trim_end(), escape(mark)),
+        }
+    }
+
+    fn solutions(&mut self, solutions: &[&Node], level: u8) -> String {
+        let mut out = format!("{} Solutions\n\n", "#".repeat(level as usize));
+        for solution in solutions {
+            out.push_str(&self.environment_block(solution, true));
+        }
+        out
+    }
+
+    fn new_line(&mut self) -> String {
+        "\n".to_string()
+    }
+
+    fn paragraph(&mut self, children: &[Node]) -> String {
+        format!("{}\n\n", self.nodes(children).trim())
+    }
+
+    fn text(&mut self, text: &str) -> String {
+        escape(text)
+    }
+
+    fn hr(&mut self, style: &HrStyle, span: Span) -> String {
+        let style = match style {
+            HrStyle::Normal => None,
+            HrStyle::Dashed => Some("dashed"),
+            HrStyle::Dotted => Some("dotted"),
+            HrStyle::Sawtooth => Some("sawtooth"),
+        };
+        if let Some(style) = style {
+            self.lossy(format!("{style} rules are written as plain ones"), span);
+        }
+        "---\n\n".to_string()
+    }
+
+    fn nbsp(&mut self) -> String {
+        "&nbsp;".to_string()
+    }
+
+    fn table_of_contents(&mut self, entries: &[TocEntry]) -> String {
+        let list = self.toc_list(entries);
+        if list.is_empty() {
+            list
+        } else {
+            format!("{list}\n")
+        }
+    }
+
+    fn reference(&mut self, _label: &str, equation: bool, target: Option<&Target>) -> String {
+        match target {
+            Some(target) => {
+                let number = headings::format_number(&target.number);
+                let number = if equation {
+                    format!("({number})")
+                } else {
+                    number
+                };
+                format!("[{number}](#{})", target.id)
+            }
+            None => "**??**".to_string(),
+        }
+    }
+}
+
+/// Renders a document to CommonMark, along with the conversions that lost something
+pub fn render(document: &Document, options: &Options) -> (String, Vec<Diagnostic>) {
+    let options = Options {
+        lang: document
+            .metadata
+            .lang()
+            .map(str::to_string)
+            .or_else(|| options.lang.clone()),
+        ..options.clone()
+    };
+    let mut renderer = CommonMarkRenderer::new(options);
+    let markdown = renderer.document(document);
+    (markdown, renderer.into_diagnostics())
+}
+
+/// "Théorème 2.3", or just the number for sections
+fn statement_name(target: &Target, lang: &str) -> String {
+    let number = headings::format_number(&target.number);
+    match target
+        .environment_type
+        .as_ref()
+        .and_then(|environment_type| environment_type.label_for(lang))
+    {
+        Some(label) if number.is_empty() => label.to_string(),
+        Some(label) => format!("{label} {number}"),
+        None => number,
+    }
+}
+
+/// Nodes rendered on lines of their own, set apart by blank lines
+fn is_block(node: &Node) -> bool {
+    matches!(
+        node,
+        Node::Header { .. }
+            | Node::List { .. }
+            | Node::ListItem(_)
+            | Node::CodeBlock { .. }
+            | Node::RawHtml(_)
+            | Node::DisplayMath { .. }
+            | Node::Env { .. }
+            | Node::Paragraph(_)
+            | Node::Hr { .. }
+            | Node::TableOfContents(_)
+            | Node::Include { .. }
+    )
+}
+
+/// Empty element giving `id` to what follows, as Markdown has no anchors of its own
+fn anchor(id: &str) -> String {
+    format!("<a id=\"{}\"></a>", html::escape_attribute(id))
+}
+
+/// `text` as the content of a blockquote
+fn quote(text: &str) -> String {
+    let mut quoted = String::new();
+    for line in text.lines() {
+        quoted.push('>');
+        if !line.is_empty() {
+            quoted.push(' ');
+            quoted.push_str(line);
+        }
+        quoted.push('\n');
+    }
+    quoted.push('\n');
+    quoted
+}
+
+/// `text` with `prefix` added to each of its non-empty lines
+fn indent(text: &str, prefix: &str) -> String {
+    let mut indented = String::new();
+    for line in text.lines() {
+        if !line.is_empty() {
+            indented.push_str(prefix);
+            indented.push_str(line);
+        }
+        indented.push('\n');
+    }
+    indented
+}
+
+fn longest_run(text: &str, c: char) -> usize {
+    text.split(|other| other != c)
+        .map(str::len)
+        .max()
+        .unwrap_or(0)
+}
+
+/// `line` with what would make it a list item or a heading underline escaped
+fn escape_line_start(line: &str) -> String {
+    if line.starts_with(['-', '+', '=']) {
+        return format!("\\{line}");
+    }
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    match line[digits..].chars().next() {
+        Some('.' | ')') if digits > 0 => format!("{}\\{}", &line[..digits], &line[digits..]),
+        _ => line.to_string(),
+    }
+}
+
+/// `value` as a YAML scalar, quoted unless it is plain words that YAML reads as a string
+fn yaml_scalar(value: &str) -> String {
+    let plain = value.starts_with(char::is_alphabetic)
+        && !value.ends_with(' ')
+        && value
+            .chars()
+            .all(|c| c.is_alphanumeric() || " -_.,()'/".contains(c))
+        && !matches!(
+            value.to_ascii_lowercase().as_str(),
+            "y" | "n" | "yes" | "no" | "on" | "off" | "true" | "false" | "null"
+        );
+    if plain {
+        return value.to_string();
+    }
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\x{:02x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// `text` with the characters that have a meaning in Markdown escaped
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(
+            c,
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' | '~' | '$' | '&'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,123 @@
+use crate::diagnostics::Diagnostic;
+use crate::include::SourceFile;
+use crate::parser::Node;
+use crate::toc::{self, TocEntry};
+use std::borrow::Cow;
+
+/// Root of a parsed file
+#[derive(Debug, Clone)]
+pub struct Document<'a> {
+    pub metadata: Metadata<'a>,
+    pub nodes: Vec<Node<'a>>,
+    /// Problems found while parsing, spans are byte ranges of the whole input or of one of `files`
+    pub diagnostics: Vec<Diagnostic>,
+    /// Files read through `%include`
+    pub files: Vec<SourceFile>,
+}
+
+impl<'a> Document<'a> {
+    pub fn toc(&self) -> Vec<TocEntry<'a>> {
+        toc::build(&self.nodes)
+    }
+
+    /// Copies every borrowed string so that the document no longer depends on the input
+    pub fn into_owned(self) -> Document<'static> {
+        Document {
+            metadata: self.metadata.into_owned(),
+            nodes: self.nodes.into_iter().map(Node::into_owned).collect(),
+            diagnostics: self.diagnostics,
+            files: self.files,
+        }
+    }
+}
+
+/// Key/value pairs from the front-matter block opening a file
+///
+/// ```text
+/// ---
+/// title: Algèbre linéaire
+/// author: Jane Doe
+/// lang: fr
+/// ---
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Metadata<'a> {
+    /// Entries in order of appearance
+    pub entries: Vec<(Cow<'a, str>, Cow<'a, str>)>,
+}
+
+impl<'a> Metadata<'a> {
+    /// Splits the front matter off `input`, returning it along with the rest of the file
+    ///
+    /// Input that does not start with a closed `---` block of `key: value` lines has no metadata,
+    /// so that a document opening with a dashed rule keeps what follows it.
+    pub fn parse(input: &'a str) -> (Metadata<'a>, &'a str) {
+        let Some(block) = input
+            .strip_prefix("---\n")
+            .or_else(|| input.strip_prefix("---\r\n"))
+        else {
+            return (Metadata::default(), input);
+        };
+
+        let mut entries = vec![];
+        let mut offset = 0;
+        for line in block.split_inclusive('\n') {
+            offset += line.len();
+            let line = line.trim_end();
+            if line == "---" && !entries.is_empty() {
+                return (Metadata { entries }, &block[offset..]);
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = line.split_once(':') else {
+                break;
+            };
+            let key = key.trim();
+            if key.is_empty() || !key.chars().all(|c| c.is_alphanumeric() || "_-".contains(c)) {
+                break;
+            }
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            entries.push((Cow::Borrowed(key), Cow::Borrowed(value)));
+        }
+
+        (Metadata::default(), input)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_ref())
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.get("title")
+    }
+    pub fn author(&self) -> Option<&str> {
+        self.get("author")
+    }
+    pub fn date(&self) -> Option<&str> {
+        self.get("date")
+    }
+    pub fn lang(&self) -> Option<&str> {
+        self.get("lang").or_else(|| self.get("language"))
+    }
+    pub fn course(&self) -> Option<&str> {
+        self.get("course")
+    }
+
+    pub fn into_owned(self) -> Metadata<'static> {
+        Metadata {
+            entries: self
+                .entries
+                .into_iter()
+                .map(|(k, v)| (Cow::Owned(k.into_owned()), Cow::Owned(v.into_owned())))
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,572 @@
+use crate::lexer::Span;
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, LazyLock};
+
+/// How an environment is presented by renderers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EnvStyle {
+    /// Statements: theorems, lemmas…
+    #[default]
+    Plain,
+    /// Definitions, examples, exercises
+    Definition,
+    /// Remarks and other asides
+    Remark,
+}
+
+impl EnvStyle {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EnvStyle::Plain => "plain",
+            EnvStyle::Definition => "definition",
+            EnvStyle::Remark => "remark",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "plain" => Some(EnvStyle::Plain),
+            "definition" => Some(EnvStyle::Definition),
+            "remark" => Some(EnvStyle::Remark),
+            _ => None,
+        }
+    }
+}
+
+/// Kind of `%name` environment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvType {
+    /// Identifier used in CSS classes and generated anchors, also accepted after `%`
+    pub name: String,
+    /// Other names accepted after `%`
+    pub aliases: Vec<String>,
+    /// Title shown before the environment's number, per language code
+    pub labels: Vec<(String, String)>,
+    /// Counter the environment is numbered with, types sharing a counter share one sequence;
+    /// `None` for unnumbered environments
+    pub counter: Option<String>,
+    pub style: EnvStyle,
+    /// Options accepted at the start of the `%name` line, on top of the common ones
+    pub params: Vec<Param>,
+    /// Collapse the content of environments of this type unless they are given `fold=false`
+    pub fold: bool,
+    /// Hide the content of environments of this type until it is clicked, depending on their
+    /// `level` and `categ`
+    pub conceal: bool,
+    /// Types of the statements environments of this type belong to, as a proof belongs to a
+    /// theorem: the closest preceding one, or the one labelled by their `of` parameter
+    pub attach: Vec<String>,
+    /// Mark ending the last line of environments of this type, such as ∎ for proofs
+    pub qed: Option<String>,
+    /// Environments of this type answer the exercise they are attached to, and can be moved to
+    /// the end of the chapter or left out of the output
+    pub solution: bool,
+}
+
+/// Kinds of the common parameters
+const FLAG: &ParamKind = &ParamKind::Flag;
+const INTEGER: &ParamKind = &ParamKind::Integer;
+const TEXT: &ParamKind = &ParamKind::Text;
+
+/// Option an environment type accepts, as `key=value` or, for flags, a bare `key`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Param {
+    pub name: String,
+    pub kind: ParamKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamKind {
+    /// `open`, `open=true` or `open=false`
+    Flag,
+    Integer,
+    Text,
+    /// Text restricted to some values
+    Choice(Vec<String>),
+}
+
+impl ParamKind {
+    /// Checks the value given to a parameter of this kind, `None` for a bare `key`
+    pub fn parse<'a>(&self, value: Option<&'a str>) -> Result<ParamValue<'a>, String> {
+        let Some(value) = value else {
+            return match self {
+                ParamKind::Flag => Ok(ParamValue::Flag(true)),
+                _ => Err("expected a value".to_string()),
+            };
+        };
+        match self {
+            ParamKind::Flag => parse_bool(value).map(ParamValue::Flag),
+            ParamKind::Integer => value
+                .parse()
+                .map(ParamValue::Integer)
+                .map_err(|_| format!("expected an integer, found `{value}`")),
+            ParamKind::Text if value.is_empty() => Err("expected a value".to_string()),
+            ParamKind::Text => Ok(ParamValue::Text(Cow::Borrowed(value))),
+            ParamKind::Choice(choices) if choices.iter().any(|choice| choice == value) => {
+                Ok(ParamValue::Text(Cow::Borrowed(value)))
+            }
+            ParamKind::Choice(choices) => Err(format!(
+                "expected one of `{}`, found `{value}`",
+                choices.join("`, `")
+            )),
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "flag" => Some(ParamKind::Flag),
+            "integer" => Some(ParamKind::Integer),
+            "text" => Some(ParamKind::Text),
+            _ if name.contains('|') => Some(ParamKind::Choice(
+                name.split('|')
+                    .map(|choice| choice.trim().to_string())
+                    .collect(),
+            )),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamValue<'a> {
+    Flag(bool),
+    Integer(i64),
+    Text(Cow<'a, str>),
+}
+
+impl ParamValue<'_> {
+    pub fn into_owned(self) -> ParamValue<'static> {
+        match self {
+            ParamValue::Flag(flag) => ParamValue::Flag(flag),
+            ParamValue::Integer(integer) => ParamValue::Integer(integer),
+            ParamValue::Text(text) => ParamValue::Text(Cow::Owned(text.into_owned())),
+        }
+    }
+}
+
+/// Options given to an environment, checked against its type's `params`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Params<'a> {
+    entries: Vec<(Cow<'a, str>, ParamValue<'a>, Span)>,
+}
+
+impl<'a> Params<'a> {
+    /// Sets `name`, written at `span`, replacing any earlier value
+    pub fn insert(&mut self, name: Cow<'a, str>, value: ParamValue<'a>, span: Span) {
+        self.entries.retain(|(n, _, _)| *n != name);
+        self.entries.push((name, value, span));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ParamValue<'a>> {
+        self.entries
+            .iter()
+            .find(|(n, _, _)| n == name)
+            .map(|(_, value, _)| value)
+    }
+
+    /// Where `name` was given
+    pub fn span(&self, name: &str) -> Option<Span> {
+        self.entries
+            .iter()
+            .find(|(n, _, _)| n == name)
+            .map(|(_, _, span)| *span)
+    }
+
+    /// Value of the flag `name`, `None` when it is not given
+    pub fn flag(&self, name: &str) -> Option<bool> {
+        match self.get(name)? {
+            ParamValue::Flag(flag) => Some(*flag),
+            _ => None,
+        }
+    }
+
+    pub fn integer(&self, name: &str) -> Option<i64> {
+        match self.get(name)? {
+            ParamValue::Integer(integer) => Some(*integer),
+            _ => None,
+        }
+    }
+
+    pub fn text(&self, name: &str) -> Option<&str> {
+        match self.get(name)? {
+            ParamValue::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &ParamValue<'a>)> {
+        self.entries
+            .iter()
+            .map(|(name, value, _)| (name.as_ref(), value))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn into_owned(self) -> Params<'static> {
+        Params {
+            entries: self
+                .entries
+                .into_iter()
+                .map(|(name, value, span)| {
+                    (Cow::Owned(name.into_owned()), value.into_owned(), span)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl EnvType {
+    pub fn new(name: &str) -> Self {
+        EnvType {
+            name: name.to_string(),
+            aliases: vec![],
+            labels: vec![],
+            counter: None,
+            style: EnvStyle::default(),
+            params: vec![],
+            fold: false,
+            conceal: false,
+            attach: vec![],
+            qed: None,
+            solution: false,
+        }
+    }
+
+    pub fn alias(mut self, alias: &str) -> Self {
+        self.aliases.push(alias.to_string());
+        self
+    }
+
+    pub fn label(mut self, lang: &str, label: &str) -> Self {
+        self.labels.push((lang.to_string(), label.to_string()));
+        self
+    }
+
+    pub fn counter(mut self, counter: &str) -> Self {
+        self.counter = Some(counter.to_string());
+        self
+    }
+
+    pub fn style(mut self, style: EnvStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    pub fn param(mut self, name: &str, kind: ParamKind) -> Self {
+        set_param(&mut self, name, kind);
+        self
+    }
+
+    pub fn fold(mut self, fold: bool) -> Self {
+        self.fold = fold;
+        self
+    }
+
+    pub fn conceal(mut self, conceal: bool) -> Self {
+        self.conceal = conceal;
+        self
+    }
+
+    pub fn attach(mut self, statement: &str) -> Self {
+        self.attach.push(statement.to_string());
+        self
+    }
+
+    pub fn qed(mut self, mark: &str) -> Self {
+        self.qed = Some(mark.to_string());
+        self
+    }
+
+    pub fn solution(mut self, solution: bool) -> Self {
+        self.solution = solution;
+        self
+    }
+
+    /// Title for `lang`, falling back to English and then to any language
+    pub fn label_for(&self, lang: &str) -> Option<&str> {
+        let primary = lang.split(['-', '_']).next().unwrap_or(lang);
+        self.labels
+            .iter()
+            .find(|(l, _)| l == lang || l == primary)
+            .or_else(|| self.labels.iter().find(|(l, _)| l == "en"))
+            .or(self.labels.first())
+            .map(|(_, label)| label.as_str())
+    }
+
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|alias| alias == name)
+    }
+
+    pub fn parameter(&self, name: &str) -> Option<&Param> {
+        self.params.iter().find(|param| param.name == name)
+    }
+
+    /// Kind of the parameter `name`, one of the type's own or a common one: `fold` for every
+    /// environment, `open` for folded ones, `level` and `categ` for concealing types and `of` for
+    /// types attached to statements
+    pub fn param_kind(&self, name: &str, folded: bool) -> Option<&ParamKind> {
+        if let Some(param) = self.parameter(name) {
+            return Some(&param.kind);
+        }
+        match name {
+            "fold" => Some(FLAG),
+            "open" if folded => Some(FLAG),
+            "level" if self.conceal => Some(INTEGER),
+            "categ" if self.conceal => Some(TEXT),
+            "of" if !self.attach.is_empty() => Some(TEXT),
+            _ => None,
+        }
+    }
+
+    /// Whether an environment of this type given `params` is collapsed
+    pub fn is_folded(&self, params: &Params) -> bool {
+        params.flag("fold").unwrap_or(self.fold)
+    }
+}
+
+/// Environment types known to the parser
+#[derive(Debug, Clone)]
+pub struct EnvTypes {
+    types: Vec<Arc<EnvType>>,
+}
+
+pub(crate) static BUILTIN: LazyLock<EnvTypes> = LazyLock::new(EnvTypes::default);
+
+impl Default for EnvTypes {
+    fn default() -> Self {
+        let theorem = |name: &str, alias: &str, en: &str, fr: &str| {
+            EnvType::new(name)
+                .alias(alias)
+                .label("en", en)
+                .label("fr", fr)
+                .counter("theorem")
+        };
+        EnvTypes::empty()
+            .with(
+                theorem("definition", "def", "Definition", "Définition")
+                    .style(EnvStyle::Definition),
+            )
+            .with(theorem("theorem", "thm", "Theorem", "Théorème"))
+            .with(theorem("corollary", "cor", "Corollary", "Corollaire"))
+            .with(theorem("lemma", "lem", "Lemma", "Lemme"))
+            .with(
+                EnvType::new("remark")
+                    .alias("rem")
+                    .label("en", "Remark")
+                    .label("fr", "Remarque")
+                    .counter("remark")
+                    .style(EnvStyle::Remark),
+            )
+            .with(
+                EnvType::new("example")
+                    .alias("eg")
+                    .label("en", "Example")
+                    .label("fr", "Exemple")
+                    .counter("example")
+                    .style(EnvStyle::Definition),
+            )
+            .with(
+                EnvType::new("exercise")
+                    .alias("ex")
+                    .alias("exo")
+                    .label("en", "Exercise")
+                    .label("fr", "Exercice")
+                    .counter("exercise")
+                    .style(EnvStyle::Definition),
+            )
+            .with(
+                EnvType::new("proof")
+                    .alias("pf")
+                    .label("en", "Proof")
+                    .label("fr", "Démonstration")
+                    .style(EnvStyle::Remark)
+                    .fold(true)
+                    .attach("theorem")
+                    .attach("lemma")
+                    .attach("corollary")
+                    .qed("∎"),
+            )
+            .with(
+                EnvType::new("solution")
+                    .alias("sol")
+                    .label("en", "Solution")
+                    .label("fr", "Solution")
+                    .style(EnvStyle::Remark)
+                    .attach("exercise")
+                    .solution(true),
+            )
+            .with(EnvType::new("fold").fold(true))
+            .with(EnvType::new("conceal").alias("block").conceal(true))
+    }
+}
+
+impl EnvTypes {
+    pub fn empty() -> Self {
+        EnvTypes { types: vec![] }
+    }
+
+    /// Adds a type, replacing any earlier one with the same name
+    pub fn with(mut self, env_type: EnvType) -> Self {
+        self.insert(env_type);
+        self
+    }
+
+    pub fn insert(&mut self, env_type: EnvType) {
+        self.types.retain(|t| t.name != env_type.name);
+        self.types.push(Arc::new(env_type));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<EnvType>> {
+        self.types.iter().rev().find(|t| t.is_named(name))
+    }
+
+    /// Type of `%name`, a generic unnumbered one when `name` is unknown
+    pub fn resolve(&self, name: &str) -> Arc<EnvType> {
+        self.get(name)
+            .cloned()
+            .unwrap_or_else(|| Arc::new(EnvType::new(name)))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<EnvType>> {
+        self.types.iter()
+    }
+
+    /// Reads types from a config file on top of the current ones
+    ///
+    /// ```text
+    /// [proposition]
+    /// aliases = prop, pr
+    /// label = Proposition
+    /// label.fr = Proposition
+    /// counter = theorem
+    /// style = plain
+    /// param.weight = integer
+    /// param.side = left | right
+    /// ```
+    ///
+    /// `numbered = true` gives a type its own counter, named after it, `fold = true` collapses its
+    /// environments by default and `conceal = true` hides them like `%conceal`. `attach = theorem,
+    /// lemma` ties environments to the statement before them, like proofs, `qed = ∎` ends them with
+    /// a mark and `solution = true` makes them solutions of what they are attached to. Parameters
+    /// are `flag`, `integer`, `text` or a `|`-separated list of choices. Sections naming an
+    /// existing type or alias amend it.
+    pub fn load(mut self, config: &str) -> Result<Self, ConfigError> {
+        let mut current: Option<EnvType> = None;
+        for (index, line) in config.lines().enumerate() {
+            let line = line.trim();
+            let error = |message: String| ConfigError {
+                line: index + 1,
+                message,
+            };
+
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                if let Some(done) = current.take() {
+                    self.insert(done);
+                }
+                let name = name.trim();
+                current = Some(match self.get(name) {
+                    Some(existing) => EnvType::clone(existing),
+                    None => EnvType::new(name),
+                });
+                continue;
+            }
+
+            let Some(env_type) = current.as_mut() else {
+                return Err(error("expected a `[name]` section first".to_string()));
+            };
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error(format!("expected `key = value`, found `{line}`")));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            match key {
+                "aliases" => env_type.aliases = split_list(value),
+                "label" => set_label(env_type, "en", value),
+                "counter" => env_type.counter = Some(value.to_string()),
+                "numbered" => {
+                    if parse_bool(value).map_err(error)? {
+                        if env_type.counter.is_none() {
+                            env_type.counter = Some(env_type.name.clone());
+                        }
+                    } else {
+                        env_type.counter = None;
+                    }
+                }
+                "fold" => env_type.fold = parse_bool(value).map_err(error)?,
+                "conceal" => env_type.conceal = parse_bool(value).map_err(error)?,
+                "attach" => env_type.attach = split_list(value),
+                "qed" => env_type.qed = Some(value.to_string()).filter(|mark| !mark.is_empty()),
+                "solution" => env_type.solution = parse_bool(value).map_err(error)?,
+                "style" => {
+                    env_type.style = EnvStyle::from_name(value)
+                        .ok_or_else(|| error(format!("unknown style `{value}`")))?
+                }
+                _ => {
+                    if let Some(lang) = key.strip_prefix("label.") {
+                        set_label(env_type, lang, value)
+                    } else if let Some(name) = key.strip_prefix("param.") {
+                        let kind = ParamKind::from_name(value)
+                            .ok_or_else(|| error(format!("unknown parameter kind `{value}`")))?;
+                        set_param(env_type, name, kind)
+                    } else {
+                        return Err(error(format!("unknown key `{key}`")));
+                    }
+                }
+            }
+        }
+        if let Some(done) = current {
+            self.insert(done);
+        }
+        Ok(self)
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("expected `true` or `false`, found `{value}`")),
+    }
+}
+
+fn set_label(env_type: &mut EnvType, lang: &str, label: &str) {
+    env_type.labels.retain(|(l, _)| l != lang);
+    env_type.labels.push((lang.to_string(), label.to_string()));
+}
+
+fn set_param(env_type: &mut EnvType, name: &str, kind: ParamKind) {
+    env_type.params.retain(|param| param.name != name);
+    env_type.params.push(Param {
+        name: name.to_string(),
+        kind,
+    });
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ConfigError {}
@@ -0,0 +1,341 @@
+use crate::document::Metadata;
+use crate::environments::ParamValue;
+use crate::lexer::HrStyle;
+use crate::parser::Node;
+use crate::{parse_nodes, ParseOptions};
+
+/// Rewrites a file in a canonical layout, keeping what it parses to
+///
+/// List items are marked with `-` and indented by two spaces per level, nested environments are
+/// opened with one more `%` than their parent and not indented, blank lines are collapsed,
+/// emphasis is written `**bold**` and `_italic_`, and paragraphs have their continuation lines
+/// unindented. Environments keep the name they were opened with, `%include` is left as written,
+/// and the front matter, math and code are kept byte for byte. Formatting the output again gives
+/// it back unchanged.
+pub fn format(input: &str, options: &ParseOptions) -> String {
+    let (_, body) = Metadata::parse(input);
+    let front_matter = &input[..input.len() - body.len()];
+    let (nodes, _) = parse_nodes(body, front_matter.len(), 0, options);
+    format!("{front_matter}{}", nodes_to_source(&nodes))
+}
+
+/// Writes a tree as source in the layout of `format`, for trees built other than by parsing
+pub fn nodes_to_source(nodes: &[Node]) -> String {
+    let mut formatter = Formatter::default();
+    formatter.nodes(nodes, Start::Implicit);
+    let body = formatter.out.trim_matches('\n');
+    match body.is_empty() {
+        true => String::new(),
+        false => format!("{body}\n"),
+    }
+}
+
+/// What a run of nodes follows, which decides how a paragraph opening it is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Start {
+    /// Start of the file or of a list item, where the parser acts as if after a blank line
+    Implicit,
+    /// Line following a `%name` line, after which a paragraph needs a blank line
+    Line,
+}
+
+#[derive(Default)]
+struct Formatter {
+    out: String,
+    /// Spaces opening each line, two per enclosing list
+    indent: usize,
+    /// Number of `%` of the innermost environment being written
+    depth: usize,
+    /// Character of the emphasis delimiters being written, innermost last
+    emphasis: Vec<char>,
+}
+
+impl Formatter {
+    /// Writes `text`, indented when it starts a line
+    fn write(&mut self, text: &str) {
+        if self.indent > 0 && self.out.ends_with('\n') && !text.is_empty() {
+            self.out.push_str(&" ".repeat(self.indent));
+        }
+        self.out.push_str(text);
+    }
+
+    fn new_lines(&mut self, count: usize) {
+        for _ in 0..count {
+            self.out.push('\n');
+        }
+    }
+
+    fn end_line(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    fn nodes(&mut self, nodes: &[Node], start: Start) {
+        let mut previous = None;
+        let mut new_lines = 0;
+        for node in nodes {
+            match node {
+                Node::NewLine => {
+                    new_lines += 1;
+                    continue;
+                }
+                Node::Paragraph(children) if is_blank(children) => continue,
+                _ => (),
+            }
+            self.new_lines(separator(previous, start, new_lines, node));
+            self.node(node);
+            previous = Some(node);
+            new_lines = 0;
+        }
+        self.new_lines(new_lines);
+    }
+
+    fn node(&mut self, node: &Node) {
+        match node {
+            Node::Header {
+                level,
+                label,
+                children,
+                ..
+            } => {
+                self.write(&"#".repeat(*level as usize));
+                self.write(" ");
+                self.nodes(children, Start::Implicit);
+                if let Some(label) = label {
+                    self.write(&format!(" {{#{}}}", label.name));
+                }
+            }
+            Node::Bold(children) => self.emphasis('*', 2, children),
+            Node::Italic(children) => self.emphasis('_', 1, children),
+            Node::Striked(children) => self.delimited("~~", children),
+            Node::Underline { children, .. } => self.delimited("..", children),
+            Node::Highlighted { children, .. } => self.delimited("||", children),
+            Node::Link { url, childen } => {
+                self.write("[");
+                self.nodes(childen, Start::Implicit);
+                self.write(&format!("]({url})"));
+            }
+            Node::List { children, .. } => {
+                for item in children {
+                    self.node(item);
+                }
+            }
+            Node::ListItem(children) => {
+                self.write("- ");
+                self.indent += 2;
+                self.nodes(children, Start::Implicit);
+                self.new_lines(end_separator(children));
+                self.end_line();
+                self.indent -= 2;
+            }
+            Node::InlineCode(code) => self.write(&format!("`{code}`")),
+            Node::CodeBlock { language, code } => {
+                self.write("```");
+                if let Some(language) = language {
+                    self.write(language);
+                    self.new_lines(1);
+                }
+                self.out.push_str(code);
+                self.out.push_str("```");
+            }
+            Node::RawHtml(html) => {
+                self.write("```{=html}\n");
+                self.out.push_str(html);
+                self.out.push_str("```");
+            }
+            Node::InlineMath { math, .. } => self.write(&format!("${math}$")),
+            Node::DisplayMath { math, label, .. } => {
+                self.write("\\[");
+                self.out.push_str(math);
+                self.out.push_str("\\]");
+                if let Some(label) = label {
+                    self.write(&format!(" {{#{}}}", label.name));
+                }
+            }
+            Node::Env {
+                name,
+                environment_arg,
+                params,
+                label,
+                children,
+                ..
+            } => {
+                self.depth += 1;
+                let marks = "%".repeat(self.depth);
+                self.write(&marks);
+                self.write(name);
+                for (name, value) in params.iter() {
+                    match value {
+                        ParamValue::Flag(true) => self.write(&format!(" {name}")),
+                        ParamValue::Flag(false) => self.write(&format!(" {name}=false")),
+                        ParamValue::Integer(integer) => self.write(&format!(" {name}={integer}")),
+                        ParamValue::Text(text) => self.write(&format!(" {name}={text}")),
+                    }
+                }
+                if let Some(title) = environment_arg {
+                    self.write(" ");
+                    self.nodes(title, Start::Implicit);
+                }
+                if let Some(label) = label {
+                    self.write(&format!(" {{#{}}}", label.name));
+                }
+                self.new_lines(1);
+                self.nodes(children, Start::Line);
+                self.new_lines(end_separator(children));
+                self.end_line();
+                self.write(&marks);
+                self.depth -= 1;
+            }
+            Node::NewLine => self.new_lines(1),
+            Node::Paragraph(children) => {
+                let first = children
+                    .iter()
+                    .position(|child| !matches!(child, Node::NewLine))
+                    .unwrap_or(children.len());
+                let last = children
+                    .iter()
+                    .rposition(|child| !matches!(child, Node::NewLine))
+                    .map_or(first, |last| last + 1);
+                self.nodes(&children[first..last], Start::Implicit);
+            }
+            Node::Text(text) => {
+                // `#` opening a line would make it a header
+                if text.starts_with('#') && (self.out.is_empty() || self.out.ends_with('\n')) {
+                    self.write(" ");
+                }
+                self.write(text);
+            }
+            Node::Hr { style, .. } => self.write(match style {
+                HrStyle::Normal => "===",
+                HrStyle::Dashed => "---",
+                HrStyle::Dotted => "...",
+                HrStyle::Sawtooth => "^^^",
+            }),
+            Node::Nbsp => self.write("~"),
+            Node::TableOfContents(_) => {
+                self.write("%toc");
+                self.new_lines(1);
+            }
+            Node::Include { path, offset, .. } => {
+                self.write("%include ");
+                if *offset != 0 {
+                    self.write(&format!("offset={offset} "));
+                }
+                self.write(path);
+                self.new_lines(1);
+            }
+            Node::Ref {
+                label, equation, ..
+            } => {
+                let command = if *equation { "eqref" } else { "ref" };
+                self.write(&format!("\\{command}{{{label}}}"));
+            }
+        }
+    }
+
+    /// Bold or italic, marked with `count` times `mark` unless that would run into an enclosing
+    /// or preceding delimiter, in which case the other one of `*` and `_` is used
+    fn emphasis(&mut self, mark: char, count: usize, children: &[Node]) {
+        let other = if mark == '*' { '_' } else { '*' };
+        let mark = if self.emphasis.last() == Some(&mark) || self.out.ends_with(mark) {
+            other
+        } else {
+            mark
+        };
+        let delimiter = mark.to_string().repeat(count);
+        self.emphasis.push(mark);
+        self.delimited(&delimiter, children);
+        self.emphasis.pop();
+    }
+
+    /// Left out when empty, as the two delimiters would read as a single doubled one
+    fn delimited(&mut self, delimiter: &str, children: &[Node]) {
+        if children.is_empty() {
+            return;
+        }
+        self.write(delimiter);
+        self.nodes(children, Start::Implicit);
+        self.write(delimiter);
+    }
+}
+
+/// Line breaks to write between `previous` and `next`, `new_lines` being the `Node::NewLine`s
+/// between them
+///
+/// Blocks are set apart by a blank line. A paragraph takes the two line breaks before it, which
+/// the parser reads as its start, and the two after it, which end it.
+fn separator(previous: Option<&Node>, start: Start, new_lines: usize, next: &Node) -> usize {
+    let next_paragraph = matches!(next, Node::Paragraph(_));
+    match previous {
+        None if next_paragraph && start == Start::Line => 2,
+        None => new_lines,
+        Some(Node::Paragraph(_)) => 2 + new_lines,
+        // Lists take the line breaks ending them, a blank line belonging to their last item
+        Some(Node::List { .. }) => new_lines,
+        _ if next_paragraph => 2,
+        // Directives take the line break ending them
+        Some(Node::TableOfContents(_) | Node::Include { .. }) => new_lines,
+        _ if new_lines > 0 => new_lines,
+        _ if starts_line(next) => 2,
+        // The parser takes the blank line after a block along with it
+        Some(previous) if is_block(previous) && is_block(next) => 2,
+        // Nothing can follow a header or the closing line of an environment on the same line
+        Some(Node::Header { .. } | Node::Env { .. }) => 2,
+        // Text following a `%name` line is not a paragraph, which a blank line would not start
+        Some(_) if start == Start::Line && is_block(next) => 2,
+        // Code, math or a rule running into text stays on its line, where it does not start a
+        // paragraph
+        Some(_) => 0,
+    }
+}
+
+/// Line breaks to write after `children` before what closes them, the closing line of their
+/// environment or the next list item
+///
+/// Both cut short any paragraph just like a block does, and a paragraph only keeps its last line
+/// break when no blank line follows it.
+fn end_separator(children: &[Node]) -> usize {
+    let mut nodes = children
+        .iter()
+        .filter(|node| !matches!(node, Node::Paragraph(children) if is_blank(children)));
+    match nodes.next_back() {
+        Some(Node::Paragraph(children)) if !matches!(children.last(), Some(Node::NewLine)) => 2,
+        None
+        | Some(
+            Node::NewLine
+            | Node::Paragraph(_)
+            | Node::List { .. }
+            | Node::TableOfContents(_)
+            | Node::Include { .. },
+        ) => 0,
+        Some(_) => 2,
+    }
+}
+
+/// Nodes taking whole lines, set apart by blank lines
+fn is_block(node: &Node) -> bool {
+    matches!(
+        node,
+        Node::Header { .. }
+            | Node::Env { .. }
+            | Node::List { .. }
+            | Node::CodeBlock { .. }
+            | Node::RawHtml(_)
+            | Node::DisplayMath { .. }
+            | Node::Hr { .. }
+    )
+}
+
+/// Blocks only read at the start of a line, unlike code, math and rules which may run into text
+fn starts_line(node: &Node) -> bool {
+    matches!(
+        node,
+        Node::Header { .. } | Node::Env { .. } | Node::List { .. }
+    )
+}
+
+/// Paragraphs made only of line breaks, left by runs of blank lines
+fn is_blank(children: &[Node]) -> bool {
+    children.iter().all(|child| matches!(child, Node::NewLine))
+}
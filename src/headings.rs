@@ -0,0 +1,115 @@
+use crate::parser::Node;
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+/// Gives every header a unique id and a hierarchical section number
+///
+/// Labelled headers are anchored at their label, generated slugs get a `-1`, `-2`… suffix when they
+/// collide with an earlier id or with any label. Numbering starts at the shallowest header level in
+/// the document.
+pub fn assign(nodes: &mut [Node]) {
+    let mut used: HashSet<String> = HashSet::new();
+    let mut top_level = u8::MAX;
+    walk(nodes, &mut |node| {
+        if let Node::Header { level, .. } = node {
+            top_level = top_level.min(*level);
+        }
+        if let Node::Header {
+            label: Some(label), ..
+        }
+        | Node::Env {
+            label: Some(label), ..
+        }
+        | Node::DisplayMath {
+            label: Some(label), ..
+        } = node
+        {
+            used.insert(label.name.to_string());
+        }
+    });
+
+    let mut counters: Vec<u32> = vec![];
+    walk(nodes, &mut |node| {
+        if let Node::Header {
+            level,
+            label,
+            id,
+            number,
+            children,
+        } = node
+        {
+            let depth = (*level - top_level) as usize + 1;
+            counters.resize(depth, 0);
+            counters[depth - 1] += 1;
+            *number = counters.clone();
+
+            if let Some(label) = label {
+                *id = Some(label.name.clone());
+            } else if id.is_none() {
+                let base = slug(&plain_text(children));
+                let mut candidate = base.clone();
+                let mut suffix = 0;
+                while used.contains(&candidate) {
+                    suffix += 1;
+                    candidate = format!("{base}-{suffix}");
+                }
+                used.insert(candidate.clone());
+                *id = Some(Cow::Owned(candidate));
+            }
+        }
+    });
+}
+
+/// Calls `f` on every node in document order, parents before their children
+pub fn walk<'a>(nodes: &mut [Node<'a>], f: &mut impl FnMut(&mut Node<'a>)) {
+    for node in nodes {
+        f(node);
+        if let Some(children) = node.children_mut() {
+            walk(children, f);
+        }
+    }
+}
+
+/// Readable text of some inline nodes, leaving out math
+pub fn plain_text(nodes: &[Node]) -> String {
+    let mut text = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(t) | Node::InlineCode(t) => text.push_str(t),
+            Node::Nbsp | Node::NewLine => text.push(' '),
+            Node::InlineMath { .. } | Node::DisplayMath { .. } | Node::Ref { .. } => (),
+            _ => text.push_str(&plain_text(node.children())),
+        }
+    }
+    text
+}
+
+/// Lowercase, dash-separated identifier keeping letters from any script
+pub fn slug(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if (c.is_whitespace() || c == '-' || c == '_')
+            && !slug.is_empty()
+            && !slug.ends_with('-')
+        {
+            slug.push('-');
+        }
+    }
+    while slug.ends_with('-') {
+        slug.pop();
+    }
+    if slug.is_empty() {
+        slug.push_str("section");
+    }
+    slug
+}
+
+pub fn format_number(number: &[u32]) -> String {
+    number
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
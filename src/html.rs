@@ -0,0 +1,587 @@
+use crate::document::{Document, Metadata};
+use crate::environments::EnvType;
+use crate::headings;
+use crate::lexer::HrStyle;
+use crate::parser::{Label, Node};
+use crate::references::Target;
+use crate::render::{self, environment_id, is_concealed, Renderer};
+use crate::toc::{self, TocEntry, TocKind};
+
+const STYLE: &str = "<style>p {padding: 1rem; border: 1px dashed red;} .math-inline{font-family: monospace; font-weight: bold; color: grey;} .math-display{font-family: monospace; font-weight: bold; color: grey; display: block; padding: 1rem; text-align: center; font-size: 2rem;} hr {margin-top: 2px solid gray;} hr.style-dashed {border-style: dashed;} hr.style-dotted {border-style: dotted;} hr.style-sawtooth {border-image: url('data:image/svg+xml,%3Csvg xmlns%3D%22http%3A//www.w3.org/2000/svg%22 viewBox%3D%220 0 12 8%22 width%3D%2212%22 height%3D%228%22%3E%3Cpath fill%3D%22none%22 stroke%3D%22rgba(191%2C191%2C191%2C0.9)%22 stroke-width%3D%221.5%22 d%3D%22M0%2C0 6%2C8 12%2C0%22/%3E%3C/svg%3E') 0 0 100% repeat; border-width: 0 0 10px; border-style: solid; position: relative;} .environment {background-color: lightgray; padding: 1rem; border: 1px solid black;} .environment-name {border-bottom: 1px solid black; margin-bottom: 1rem;} .environment-label {font-weight: bold;} details.environment > summary {cursor: pointer;} details.environment:not([open]) > summary {border-bottom: none; margin-bottom: 0;} .conceal-hidden {filter: blur(0.5rem); cursor: pointer; user-select: none;} .equation-number {float: right;} .qed {float: right; margin-left: 1rem;} .solution-link {text-align: right;} .ref-unresolved {color: red;} .anchor {margin-left: 0.5rem; text-decoration: none; color: lightgray; visibility: hidden;} :hover > .anchor {visibility: visible;} .toc-sidebar {float: right; position: sticky; top: 0; max-width: 20rem; border: 1px solid black; padding: 1rem;}</style>";
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Wrap the output in a full page with the default stylesheet
+    pub standalone: bool,
+    /// Page to render into instead of the default one: `{{body}}` is replaced by the rendered
+    /// document, `{{toc}}` by its table of contents and `{{key}}` by the front-matter value for
+    /// `key`
+    pub template: Option<String>,
+    /// Prefix headers with their hierarchical section number
+    pub number_sections: bool,
+    /// Deepest section level listed in tables of contents, all of them when `None`
+    pub toc_depth: Option<usize>,
+    /// Put a table of contents beside the document, in addition to `%toc` markers
+    pub toc_sidebar: bool,
+    /// List numbered environments in tables of contents
+    pub toc_environments: bool,
+    /// Language of generated labels when the front matter sets none, English by default
+    pub lang: Option<String>,
+    /// What to do with the content of `%conceal` blocks
+    pub conceal: ConcealMode,
+    /// Only conceal blocks up to this `level`, those without one counting as level 1
+    pub conceal_level: Option<i64>,
+    /// Only conceal blocks whose `categ` is one of these, all blocks when empty
+    pub conceal_categories: Vec<String>,
+    /// Where to put `%sol` environments
+    pub solutions: SolutionMode,
+    /// Drop raw HTML and links to `javascript:` or `data:` URLs, for untrusted documents
+    ///
+    /// The dialect has no link or image syntax, so links only come from `from_commonmark`, which
+    /// keeps just the description of images, or from trees built by hand. This only covers
+    /// rendering: parse untrusted input with `ParseOptions::safe` as well, which refuses
+    /// `%include` and bounds its size and nesting.
+    pub safe: bool,
+}
+
+/// Treatment of the `%conceal` blocks selected by `Options`, the others being shown as is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConcealMode {
+    /// Blur the content until it is clicked
+    #[default]
+    Hide,
+    /// Show the content
+    Reveal,
+    /// Leave the blocks out, e.g. for a blank exercise sheet
+    Strip,
+}
+
+/// The HTML backend, which keeps the default of every `Renderer` hook
+#[derive(Debug, Clone, Default)]
+pub struct HtmlRenderer {
+    options: Options,
+}
+
+impl HtmlRenderer {
+    pub fn new(options: Options) -> Self {
+        Self { options }
+    }
+}
+
+impl Renderer for HtmlRenderer {
+    fn options(&self) -> &Options {
+        &self.options
+    }
+}
+
+pub fn render(document: &Document, options: &Options) -> String {
+    let options = Options {
+        lang: document
+            .metadata
+            .lang()
+            .map(str::to_string)
+            .or_else(|| options.lang.clone()),
+        ..options.clone()
+    };
+    HtmlRenderer::new(options).document(document)
+}
+
+pub fn nodes_to_html(nodes: &[Node], options: &Options) -> String {
+    HtmlRenderer::new(options.clone()).nodes(nodes)
+}
+
+/// Default of `Renderer::document`
+pub fn document<R: Renderer + ?Sized>(renderer: &mut R, document: &Document) -> String {
+    let metadata = &document.metadata;
+    let mut body = match renderer.options().solutions {
+        SolutionMode::Collect => render::chapters(renderer, &document.nodes),
+        _ => renderer.nodes(&document.nodes),
+    };
+    let toc = toc_list(renderer, &toc::build(&document.nodes));
+    let options = renderer.options();
+    if options.toc_sidebar && !toc.is_empty() {
+        body = format!("<nav class=\"toc toc-sidebar\">{toc}</nav>{body}");
+    }
+
+    if let Some(template) = &options.template {
+        fill_template(template, metadata, &body, &toc)
+    } else if options.standalone {
+        let lang = match metadata.lang() {
+            Some(lang) => format!(" lang=\"{}\"", escape_attribute(lang)),
+            None => String::new(),
+        };
+        let mut head = String::from("<meta charset=\"utf-8\"/>");
+        if let Some(title) = metadata.title() {
+            head.push_str(&format!("<title>{}</title>", escape_text(title)));
+        }
+        for (key, value) in &metadata.entries {
+            if !matches!(key.as_ref(), "title" | "lang" | "language") {
+                head.push_str(&format!(
+                    "<meta name=\"{}\" content=\"{}\"/>",
+                    escape_attribute(key),
+                    escape_attribute(value)
+                ));
+            }
+        }
+        format!("<!DOCTYPE html><html{lang}><head>{head}{STYLE}</head><body>{body}</body></html>")
+    } else {
+        body
+    }
+}
+
+/// Default of `Renderer::solutions`
+pub fn solutions<R: Renderer + ?Sized>(renderer: &mut R, solutions: &[&Node], level: u8) -> String {
+    let solutions: String = solutions
+        .iter()
+        .map(|solution| environment_block(renderer, solution, true))
+        .collect();
+    format!("<section class=\"solutions\"><h{level}>Solutions</h{level}>{solutions}</section>")
+}
+
+fn fill_template(template: &str, metadata: &Metadata, body: &str, toc: &str) -> String {
+    let mut out = String::with_capacity(template.len() + body.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        match rest[start + 2..start + end].trim() {
+            "body" => out.push_str(body),
+            "toc" => out.push_str(toc),
+            key => out.push_str(&escape_attribute(metadata.get(key).unwrap_or_default())),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Default of `Renderer::table_of_contents`
+pub fn table_of_contents<R: Renderer + ?Sized>(renderer: &mut R, entries: &[TocEntry]) -> String {
+    format!("<nav class=\"toc\">{}</nav>", toc_list(renderer, entries))
+}
+
+fn toc_list<R: Renderer + ?Sized>(renderer: &mut R, entries: &[TocEntry]) -> String {
+    let mut items = String::new();
+    for entry in entries {
+        let options = renderer.options();
+        let title = match &entry.kind {
+            TocKind::Section if options.toc_depth.is_some_and(|depth| entry.depth() > depth) => {
+                continue
+            }
+            TocKind::Section if options.number_sections => format!(
+                "<span class=\"section-number\">{}</span> {}",
+                headings::format_number(&entry.number),
+                renderer.nodes(&entry.title)
+            ),
+            TocKind::Section => renderer.nodes(&entry.title),
+            TocKind::Environment(_) if !options.toc_environments => continue,
+            TocKind::Environment(environment_type) => environment_title(
+                renderer,
+                environment_type,
+                &entry.number,
+                Some(&entry.title).filter(|title| !title.is_empty()),
+                None,
+            ),
+        };
+        items.push_str(&format!(
+            "<li><a href=\"#{}\">{title}</a>{}</li>",
+            escape_attribute(&entry.id),
+            toc_list(renderer, &entry.children)
+        ));
+    }
+    if items.is_empty() {
+        items
+    } else {
+        format!("<ul>{items}</ul>")
+    }
+}
+
+fn lang<R: Renderer + ?Sized>(renderer: &R) -> String {
+    renderer
+        .options()
+        .lang
+        .as_deref()
+        .unwrap_or("en")
+        .to_string()
+}
+
+/// "Théorème 2.3 (Caractérisation du rang)", or "Démonstration (Théorème 2.3)" for a proof of a
+/// given statement
+fn environment_title<R: Renderer + ?Sized>(
+    renderer: &mut R,
+    environment_type: &EnvType,
+    number: &[u32],
+    arg: Option<&Vec<Node>>,
+    statement: Option<&Target>,
+) -> String {
+    let lang = lang(renderer);
+    let mut details = vec![];
+    if let Some(statement) = statement {
+        details.push(format!(
+            "<a class=\"ref\" href=\"#{}\">{}</a>",
+            escape_attribute(&statement.id),
+            statement_name(statement, &lang)
+        ));
+    }
+    if let Some(arg) = arg {
+        details.push(renderer.nodes(arg));
+    }
+    let details = details.join(", ");
+
+    match environment_type.label_for(&lang) {
+        Some(label) => {
+            let mut title = escape_text(label);
+            if !number.is_empty() {
+                title.push(' ');
+                title.push_str(&headings::format_number(number));
+            }
+            let mut title = format!("<span class=\"environment-label\">{title}</span>");
+            if !details.is_empty() {
+                title.push_str(&format!(" ({details})"));
+            }
+            title
+        }
+        None => details,
+    }
+}
+
+/// "Théorème 2.3", or just the number for sections
+fn statement_name(target: &Target, lang: &str) -> String {
+    let number = headings::format_number(&target.number);
+    match target
+        .environment_type
+        .as_ref()
+        .and_then(|environment_type| environment_type.label_for(lang))
+    {
+        Some(label) if number.is_empty() => escape_text(label),
+        Some(label) => format!("{} {number}", escape_text(label)),
+        None => number,
+    }
+}
+
+/// Default of `Renderer::nodes_with_qed`, which puts `mark` inside the last paragraph, list item
+/// or equation rather than after it
+pub fn nodes_with_qed<R: Renderer + ?Sized>(
+    renderer: &mut R,
+    nodes: &[Node],
+    mark: &str,
+) -> String {
+    let mark_html = format!("<span class=\"qed\">{}</span>", escape_text(mark));
+    let Some(last) = nodes
+        .iter()
+        .rposition(|node| !matches!(node, Node::NewLine))
+    else {
+        return format!("{}{mark_html}", renderer.nodes(nodes));
+    };
+    let last_html = match &nodes[last] {
+        Node::Paragraph(children) => {
+            format!("<p>{}</p>", renderer.nodes_with_qed(children, mark))
+        }
+        Node::List { children, .. } => {
+            format!("<ul>{}</ul>", renderer.nodes_with_qed(children, mark))
+        }
+        Node::ListItem(children) => {
+            format!("<li>{}</li>", renderer.nodes_with_qed(children, mark))
+        }
+        Node::DisplayMath {
+            math,
+            label,
+            number,
+            ..
+        } => display_math(math, label.as_ref(), number, &mark_html),
+        node => format!("{}{mark_html}", renderer.node(node)),
+    };
+    format!(
+        "{}{last_html}{}",
+        renderer.nodes(&nodes[..last]),
+        renderer.nodes(&nodes[last + 1..])
+    )
+}
+
+/// Display math, followed by `after` inside the block
+pub fn display_math(math: &str, label: Option<&Label>, number: &[u32], after: &str) -> String {
+    let id = match label {
+        Some(label) => format!(" id=\"{}\"", escape_attribute(&label.name)),
+        None => String::new(),
+    };
+    let number = if number.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "<span class=\"equation-number\">({})</span>",
+            headings::format_number(number)
+        )
+    };
+    format!(
+        "<span class=\"math-display\"{id}>{}{number}{after}</span>",
+        escape_text(math)
+    )
+}
+
+/// Placement of solutions, the environments of `EnvType::solution` types
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SolutionMode {
+    /// Where they are written
+    #[default]
+    Inline,
+    /// At the end of their chapter, each linking back to its exercise, with a link to it left in
+    /// place
+    Collect,
+    /// Nowhere, e.g. for a student sheet
+    Strip,
+}
+
+/// Default of `Renderer::environment`, which leaves a link to solutions moved away by
+/// `SolutionMode::Collect` and drops those of `SolutionMode::Strip`
+pub fn environment<R: Renderer + ?Sized>(renderer: &mut R, node: &Node) -> String {
+    let options = renderer.options();
+    match node {
+        Node::Env {
+            environment_type, ..
+        } if environment_type.solution && options.solutions != SolutionMode::Inline => {
+            match (options.solutions, environment_id(node)) {
+                (SolutionMode::Collect, Some(id)) => format!(
+                    "<p class=\"solution-link\"><a href=\"#{}\">{}</a></p>",
+                    escape_attribute(&id),
+                    escape_text(
+                        environment_type
+                            .label_for(options.lang.as_deref().unwrap_or("en"))
+                            .unwrap_or(&environment_type.name)
+                    )
+                ),
+                _ => String::new(),
+            }
+        }
+        _ => environment_block(renderer, node, false),
+    }
+}
+
+/// Renders an environment, with a link to the statement it is attached to when `link_statement`
+/// is set or when it is given with `of`
+fn environment_block<R: Renderer + ?Sized>(
+    renderer: &mut R,
+    node: &Node,
+    link_statement: bool,
+) -> String {
+    let Node::Env {
+        environment_type,
+        environment_arg,
+        params,
+        attached,
+        number,
+        children,
+        ..
+    } = node
+    else {
+        return renderer.node(node);
+    };
+    let conceal = renderer.options().conceal;
+    let concealed = environment_type.conceal && is_concealed(params, renderer.options());
+    if concealed && conceal == ConcealMode::Strip {
+        return String::new();
+    }
+    let statement = attached
+        .as_ref()
+        .filter(|_| link_statement || params.text("of").is_some());
+    let title = environment_title(
+        renderer,
+        environment_type,
+        number,
+        environment_arg.as_ref(),
+        statement,
+    );
+    let class = format!(
+        "environment environment-{} environment-style-{}",
+        escape_attribute(&environment_type.name),
+        environment_type.style.name()
+    );
+    let id = match environment_id(node) {
+        Some(id) => format!(" id=\"{}\"", escape_attribute(&id)),
+        None => String::new(),
+    };
+    let mut children = match &environment_type.qed {
+        Some(mark) => renderer.nodes_with_qed(children, mark),
+        None => renderer.nodes(children),
+    };
+    if concealed && conceal == ConcealMode::Hide {
+        let mut data = String::new();
+        if let Some(level) = params.integer("level") {
+            data.push_str(&format!(" data-level=\"{level}\""));
+        }
+        if let Some(categ) = params.text("categ") {
+            data.push_str(&format!(" data-categ=\"{}\"", escape_attribute(categ)));
+        }
+        children = format!(
+            "<div class=\"conceal conceal-hidden\"{data} tabindex=\"0\" onclick=\"this.classList.remove('conceal-hidden')\">{children}</div>"
+        );
+    }
+    if environment_type.is_folded(params) {
+        let open = if params.flag("open") == Some(true) {
+            " open"
+        } else {
+            ""
+        };
+        let summary = if title.is_empty() {
+            String::new()
+        } else {
+            format!("<summary class=\"environment-name\">{title}</summary>")
+        };
+        format!("<details class=\"{class}\"{id}{open}>{summary}{children}</details>")
+    } else {
+        let env_name = if title.is_empty() {
+            String::new()
+        } else {
+            format!("<div class=\"environment-name\">{title}</div>")
+        };
+        format!("<div class=\"{class}\"{id}>{env_name}{children}</div>")
+    }
+}
+
+/// Default of `Renderer::header`
+pub fn header<R: Renderer + ?Sized>(
+    renderer: &mut R,
+    level: u8,
+    id: Option<&str>,
+    number: &[u32],
+    children: &[Node],
+) -> String {
+    let number = if renderer.options().number_sections && !number.is_empty() {
+        format!(
+            "<span class=\"section-number\">{}</span> ",
+            headings::format_number(number)
+        )
+    } else {
+        String::new()
+    };
+    let children = renderer.nodes(children);
+    match id {
+        Some(id) => {
+            let id = escape_attribute(id);
+            format!(
+                "<h{level} id=\"{id}\">{number}{children}<a class=\"anchor\" href=\"#{id}\">#</a></h{level}>"
+            )
+        }
+        None => format!("<h{level}>{number}{children}</h{level}>"),
+    }
+}
+
+/// `children` inside a `tag` element
+pub fn wrap<R: Renderer + ?Sized>(renderer: &mut R, tag: &str, children: &[Node]) -> String {
+    format!("<{tag}>{}</{tag}>", renderer.nodes(children))
+}
+
+/// Default of `Renderer::link`, which keeps only the text of links to unsafe URLs in safe mode
+pub fn link<R: Renderer + ?Sized>(renderer: &mut R, url: &str, children: &[Node]) -> String {
+    if renderer.options().safe && !is_safe_url(url) {
+        return renderer.nodes(children);
+    }
+    format!(
+        "<a href=\"{}\">{}</a>",
+        escape_attribute(url),
+        renderer.nodes(children)
+    )
+}
+
+/// Whether `url` has none of the schemes that run code or embed content, which browsers match
+/// ignoring case, whitespace and control characters
+pub fn is_safe_url(url: &str) -> bool {
+    let scheme: String = url
+        .chars()
+        .take_while(|&c| c != ':')
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    !url.contains(':') || !matches!(scheme.as_str(), "javascript" | "vbscript" | "data")
+}
+
+/// Default of `Renderer::raw_html`, which drops it in safe mode
+pub fn raw_html<R: Renderer + ?Sized>(renderer: &mut R, html: &str) -> String {
+    if renderer.options().safe {
+        String::new()
+    } else {
+        html.to_string()
+    }
+}
+
+pub fn inline_code(code: &str) -> String {
+    format!("<code class=\"inline\">{}</code>", escape_text(code))
+}
+
+pub fn code_block(language: Option<&str>, code: &str) -> String {
+    let lang = match language {
+        Some(language) => format!(" lang=\"{}\"", escape_attribute(language)),
+        None => String::new(),
+    };
+    format!(
+        "<pre><code class=\"block\"{lang}>{}</code></pre>",
+        escape_text(code)
+    )
+}
+
+pub fn inline_math(math: &str) -> String {
+    format!("<span class=\"math-inline\">{}</span>", escape_text(math))
+}
+
+pub fn hr(style: &HrStyle) -> String {
+    let style = match style {
+        HrStyle::Normal => "normal",
+        HrStyle::Dashed => "dashed",
+        HrStyle::Dotted => "dotted",
+        HrStyle::Sawtooth => "sawtooth",
+    };
+    format!("<hr class=\"style-{style}\"/>")
+}
+
+/// Default of `Renderer::reference`, a link showing the target's number
+pub fn reference(label: &str, equation: bool, target: Option<&Target>) -> String {
+    match target {
+        Some(target) => {
+            let number = headings::format_number(&target.number);
+            format!(
+                "<a class=\"ref\" href=\"#{}\">{}</a>",
+                escape_attribute(&target.id),
+                if equation {
+                    format!("({number})")
+                } else {
+                    number
+                }
+            )
+        }
+        None => format!(
+            "<span class=\"ref ref-unresolved\" title=\"{}\">??</span>",
+            escape_attribute(label)
+        ),
+    }
+}
+
+/// `text` as element content, where only `&`, `<` and `>` are special
+pub fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// `text` as a quoted attribute value, quotes escaped as well
+pub fn escape_attribute(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
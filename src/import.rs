@@ -0,0 +1,1076 @@
+use crate::diagnostics::Diagnostic;
+use crate::environments::{EnvTypes, Params};
+use crate::lexer::{HrStyle, Span};
+use crate::parser::{ListType, Node, OwnedNode};
+use crate::ParseOptions;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Converts a CommonMark body, which starts `offset` bytes into its file, into this dialect's
+/// tree
+///
+/// Headings, rules, fenced and indented code, HTML blocks, `-`, `*`, `+` and ordered lists,
+/// emphasis, strikethrough, code spans, links and autolinks are read the CommonMark way; link
+/// reference definitions and tables are left as text. Where a construct has no counterpart here,
+/// or text would be read differently by this dialect's lexer, a warning is given: `~~~` fences,
+/// ordered lists, blockquotes (which become `%quote` environments), links and images, and literal
+/// `%`, `$`, `_`, `~` and the like that would open markup once written out. In safe mode, content
+/// nested deeper than the limit is left out with an error, as the parser does.
+pub fn commonmark(
+    input: &str,
+    offset: usize,
+    options: &ParseOptions,
+) -> (Vec<OwnedNode>, Vec<Diagnostic>) {
+    let mut lines = vec![];
+    let mut start = offset;
+    for line in input.split_inclusive('\n') {
+        let text = line.strip_suffix('\n').unwrap_or(line);
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        lines.push(Line {
+            text,
+            offset: start,
+        });
+        start += line.len();
+    }
+    let mut importer = Importer {
+        environments: &options.environments,
+        max_depth: options.safe.map(|limits| limits.max_depth),
+        depth: 0,
+        diagnostics: vec![],
+    };
+    let nodes = importer.blocks(&lines);
+    (nodes, importer.diagnostics)
+}
+
+/// Line of the input, or what is left of it inside a container
+#[derive(Debug, Clone, Copy)]
+struct Line<'a> {
+    text: &'a str,
+    /// Where `text` starts in the file
+    offset: usize,
+}
+
+impl<'a> Line<'a> {
+    fn is_blank(&self) -> bool {
+        self.text.trim().is_empty()
+    }
+
+    fn indent(&self) -> usize {
+        indent(self.text)
+    }
+
+    fn skip(self, bytes: usize) -> Line<'a> {
+        Line {
+            text: &self.text[bytes..],
+            offset: self.offset + bytes,
+        }
+    }
+
+    fn trim_start(self) -> Line<'a> {
+        self.skip(self.text.len() - self.text.trim_start().len())
+    }
+
+    /// Line with up to `width` columns of leading whitespace removed
+    fn unindent(self, width: usize) -> Line<'a> {
+        let mut column = 0;
+        let mut bytes = 0;
+        for c in self.text.chars() {
+            let next = match c {
+                ' ' => column + 1,
+                '\t' => column + 4 - column % 4,
+                _ => break,
+            };
+            if next > width {
+                break;
+            }
+            column = next;
+            bytes += 1;
+        }
+        self.skip(bytes)
+    }
+
+    fn span(&self, range: Range<usize>) -> Span {
+        Span::new(self.offset + range.start, self.offset + range.end)
+    }
+}
+
+struct Importer<'o> {
+    environments: &'o EnvTypes,
+    max_depth: Option<usize>,
+    /// Containers and inline formatting enclosing what is being read
+    depth: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Importer<'_> {
+    fn warn(&mut self, message: impl Into<String>, span: Span) {
+        self.diagnostics.push(Diagnostic::warning(message, span));
+    }
+
+    fn blocks(&mut self, lines: &[Line]) -> Vec<OwnedNode> {
+        if let Some(max_depth) = self.max_depth.filter(|&max_depth| self.depth > max_depth) {
+            if let Some(line) = lines.iter().find(|line| !line.is_blank()) {
+                self.diagnostics.push(Diagnostic::error(
+                    format!("content nested more than {max_depth} levels deep is left out"),
+                    line.span(0..line.text.len()),
+                ));
+            }
+            return vec![];
+        }
+        let mut nodes = vec![];
+        let mut paragraph = vec![];
+        let mut i = 0;
+        while i < lines.len() {
+            let line = lines[i];
+            if line.is_blank() {
+                self.paragraph(&mut paragraph, &mut nodes);
+                i += 1;
+                continue;
+            }
+            if line.indent() >= 4 {
+                if paragraph.is_empty() {
+                    i = self.indented_code(lines, i, &mut nodes);
+                } else {
+                    paragraph.push(line.trim_start());
+                    i += 1;
+                }
+                continue;
+            }
+            let block = line.trim_start();
+            let text = block.text;
+            if let Some(level) = setext(text).filter(|_| !paragraph.is_empty()) {
+                let header = self.header(level, &paragraph);
+                nodes.push(header);
+                paragraph.clear();
+                i += 1;
+                continue;
+            }
+            if fence(text).is_some() {
+                self.paragraph(&mut paragraph, &mut nodes);
+                i = self.fenced_code(lines, i, &mut nodes);
+                continue;
+            }
+            if let Some((level, content)) = atx(block) {
+                self.paragraph(&mut paragraph, &mut nodes);
+                let header = self.header(level, &[content]);
+                nodes.push(header);
+                i += 1;
+                continue;
+            }
+            if thematic_break(text) {
+                self.paragraph(&mut paragraph, &mut nodes);
+                nodes.push(Node::Hr {
+                    style: HrStyle::Normal,
+                    span: block.span(0..text.trim_end().len()),
+                });
+                i += 1;
+                continue;
+            }
+            if text.starts_with('>') {
+                self.paragraph(&mut paragraph, &mut nodes);
+                i = self.quote(lines, i, &mut nodes);
+                continue;
+            }
+            if let Some(marker) = list_marker(text) {
+                // Only lists that cannot be mistaken for text may interrupt a paragraph
+                if paragraph.is_empty() || (!marker.blank && (!marker.ordered || marker.start == 1))
+                {
+                    self.paragraph(&mut paragraph, &mut nodes);
+                    i = self.list(lines, i, &mut nodes);
+                    continue;
+                }
+            }
+            if html_start(text) && paragraph.is_empty() {
+                i = self.html(lines, i, &mut nodes);
+                continue;
+            }
+            paragraph.push(block);
+            i += 1;
+        }
+        self.paragraph(&mut paragraph, &mut nodes);
+        nodes
+    }
+
+    /// Blocks inside a blockquote or list item
+    fn nested_blocks(&mut self, lines: &[Line]) -> Vec<OwnedNode> {
+        self.depth += 1;
+        let nodes = self.blocks(lines);
+        self.depth -= 1;
+        nodes
+    }
+
+    /// Ends the paragraph made of `lines`, if any
+    fn paragraph(&mut self, lines: &mut Vec<Line>, nodes: &mut Vec<OwnedNode>) {
+        if !lines.is_empty() {
+            nodes.push(Node::Paragraph(self.inlines(lines)));
+            lines.clear();
+        }
+    }
+
+    fn header(&mut self, level: u8, lines: &[Line]) -> OwnedNode {
+        Node::Header {
+            level,
+            label: None,
+            id: None,
+            number: vec![],
+            children: self.inlines(lines),
+        }
+    }
+
+    fn inlines(&mut self, lines: &[Line]) -> Vec<OwnedNode> {
+        let mut text = String::new();
+        let mut starts = vec![];
+        for line in lines {
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            starts.push((text.len(), line.offset));
+            text.push_str(line.text);
+        }
+        let end = text.trim_end().len();
+        let mut inlines = Inlines {
+            text: &text,
+            starts,
+            diagnostics: &mut self.diagnostics,
+            warned: 0,
+            max_depth: self.max_depth,
+            depth: self.depth,
+            brackets: matching_brackets(&text),
+            unclosed: HashMap::new(),
+        };
+        inlines.parse(0..end)
+    }
+
+    /// Code block of the lines indented by four columns starting at `start`, returning the line
+    /// following it
+    fn indented_code(&mut self, lines: &[Line], start: usize, nodes: &mut Vec<OwnedNode>) -> usize {
+        let mut end = start;
+        for (i, line) in lines.iter().enumerate().skip(start) {
+            if !line.is_blank() && line.indent() < 4 {
+                break;
+            }
+            if !line.is_blank() {
+                end = i + 1;
+            }
+        }
+        let code: String = lines[start..end]
+            .iter()
+            .map(|line| format!("{}\n", line.unindent(4).text))
+            .collect();
+        let first = lines[start].trim_start();
+        self.warn(
+            "indented code is written as a fenced block in this dialect",
+            first.span(0..first.text.len()),
+        );
+        self.code_block(first, "", code, nodes);
+        end
+    }
+
+    /// Code block fenced on line `start`, returning the line following its closing fence
+    fn fenced_code(&mut self, lines: &[Line], start: usize, nodes: &mut Vec<OwnedNode>) -> usize {
+        let indent = lines[start].indent();
+        let opening = lines[start].trim_start();
+        let (mark, width) = fence(opening.text).unwrap_or(('`', 3));
+        if mark == '~' {
+            self.warn(
+                "`~~~` fences are written with backticks in this dialect",
+                opening.span(0..width),
+            );
+        }
+        let mut code = String::new();
+        let mut i = start + 1;
+        while i < lines.len() {
+            let line = lines[i];
+            i += 1;
+            let closing = line.trim_start();
+            if line.indent() < 4
+                && fence(closing.text).is_some_and(|(m, w)| {
+                    m == mark && w >= width && closing.text[w..].trim().is_empty()
+                })
+            {
+                break;
+            }
+            code.push_str(line.unindent(indent).text);
+            code.push('\n');
+        }
+        self.code_block(opening, opening.text[width..].trim(), code, nodes);
+        i
+    }
+
+    fn code_block(&mut self, line: Line, info: &str, code: String, nodes: &mut Vec<OwnedNode>) {
+        if code.contains("```") {
+            self.warn(
+                "code containing ``` cannot be written in this dialect",
+                line.span(0..line.text.len()),
+            );
+        }
+        nodes.push(match info {
+            "" => Node::CodeBlock {
+                language: None,
+                code: Cow::Owned(format!("\n{code}")),
+            },
+            info => Node::CodeBlock {
+                language: Some(Cow::Owned(info.to_string())),
+                code: Cow::Owned(code),
+            },
+        });
+    }
+
+    /// HTML block starting on line `start` and running to the next blank line
+    fn html(&mut self, lines: &[Line], start: usize, nodes: &mut Vec<OwnedNode>) -> usize {
+        let end = lines[start..]
+            .iter()
+            .position(Line::is_blank)
+            .map_or(lines.len(), |length| start + length);
+        let html: String = lines[start..end]
+            .iter()
+            .map(|line| format!("{}\n", line.text))
+            .collect();
+        nodes.push(Node::RawHtml(Cow::Owned(html)));
+        end
+    }
+
+    /// Blockquote starting on line `start`, turned into a `%quote` environment
+    fn quote(&mut self, lines: &[Line], start: usize, nodes: &mut Vec<OwnedNode>) -> usize {
+        let mut inner: Vec<Line> = vec![];
+        let mut i = start;
+        while i < lines.len() {
+            let line = lines[i];
+            let block = line.trim_start();
+            if line.indent() < 4 && block.text.starts_with('>') {
+                let rest = block.skip(1);
+                let rest = match rest.text.starts_with([' ', '\t']) {
+                    true => rest.skip(1),
+                    false => rest,
+                };
+                inner.push(rest);
+            } else if !line.is_blank()
+                && inner.last().is_some_and(|last| !last.is_blank())
+                && !is_block_start(line)
+            {
+                // Lazy continuation of the quoted paragraph
+                inner.push(block);
+            } else {
+                break;
+            }
+            i += 1;
+        }
+        let opening = lines[start].trim_start();
+        self.warn(
+            "blockquotes become `%quote` environments in this dialect",
+            opening.span(0..1),
+        );
+        let children = environment_children(self.nested_blocks(&inner));
+        nodes.push(Node::Env {
+            environment_type: self.environments.resolve("quote"),
+            name: Cow::Borrowed("quote"),
+            span: opening.span(0..1),
+            environment_arg: None,
+            params: Params::default(),
+            attached: None,
+            label: None,
+            id: None,
+            number: vec![],
+            children,
+        });
+        i
+    }
+
+    /// List whose first item is on line `start`
+    fn list(&mut self, lines: &[Line], start: usize, nodes: &mut Vec<OwnedNode>) -> usize {
+        let opening = lines[start].trim_start();
+        let Some(first) = list_marker(opening.text) else {
+            return start;
+        };
+        if first.ordered {
+            self.warn(
+                "ordered lists become bulleted ones in this dialect",
+                opening.span(0..first.width),
+            );
+        }
+        let mut items = vec![];
+        let mut loose = false;
+        let mut blank_before = false;
+        let mut i = start;
+        while i < lines.len() {
+            let line = lines[i];
+            let block = line.trim_start();
+            if line.indent() >= 4 || thematic_break(block.text) {
+                break;
+            }
+            let Some(marker) = list_marker(block.text).filter(|m| m.kind == first.kind) else {
+                break;
+            };
+            loose |= blank_before;
+            let after = block.skip(marker.width);
+            let spaces = indent(after.text);
+            let (content, width) = if marker.blank || spaces > 4 {
+                (after.unindent(1), line.indent() + marker.width + 1)
+            } else {
+                (after.trim_start(), line.indent() + marker.width + spaces)
+            };
+            let mut item = vec![content];
+            i += 1;
+            while i < lines.len() {
+                let next = lines[i];
+                if next.is_blank() {
+                    item.push(next.skip(next.text.len()));
+                } else if next.indent() >= width {
+                    item.push(next.unindent(width));
+                } else if item.last().is_some_and(|last| !last.is_blank()) && !is_block_start(next)
+                {
+                    item.push(next.trim_start());
+                } else {
+                    break;
+                }
+                i += 1;
+            }
+            blank_before = false;
+            while item.len() > 1 && item.last().is_some_and(Line::is_blank) {
+                item.pop();
+                blank_before = true;
+            }
+            loose |= item.iter().skip(1).any(Line::is_blank);
+            items.push(self.nested_blocks(&item));
+        }
+        let children = items
+            .into_iter()
+            .map(|children| match loose {
+                true => Node::ListItem(children),
+                false => Node::ListItem(tight_item_children(children)),
+            })
+            .collect();
+        nodes.push(Node::List {
+            list_type: ListType::Normal,
+            children,
+        });
+        i
+    }
+}
+
+/// Content of a tight list item laid out the way the parser reads `- a` lines: paragraphs run
+/// into what follows them, and those followed by a block are bare text
+fn tight_item_children(children: Vec<OwnedNode>) -> Vec<OwnedNode> {
+    let count = children.len();
+    let mut laid_out = vec![];
+    for (i, child) in children.into_iter().enumerate() {
+        match child {
+            Node::Paragraph(mut paragraph) => {
+                paragraph.push(Node::NewLine);
+                match i + 1 < count {
+                    true => laid_out.extend(paragraph),
+                    false => laid_out.push(Node::Paragraph(paragraph)),
+                }
+            }
+            child => laid_out.push(child),
+        }
+    }
+    laid_out
+}
+
+/// Content of an environment laid out the way the parser reads a `%name` line followed by lines
+/// of text: the first paragraph runs from the opening line and the last one into the closing one
+fn environment_children(mut children: Vec<OwnedNode>) -> Vec<OwnedNode> {
+    if let Some(Node::Paragraph(last)) = children.last_mut() {
+        last.push(Node::NewLine);
+    }
+    if let Some(Node::Paragraph(_)) = children.first() {
+        if let Node::Paragraph(first) = children.remove(0) {
+            children.splice(0..0, first);
+        }
+    }
+    children
+}
+
+/// Inline content of a paragraph or heading, its lines joined by `\n`
+struct Inlines<'t> {
+    text: &'t str,
+    /// Where each line starts in `text`, and in the file
+    starts: Vec<(usize, usize)>,
+    diagnostics: &'t mut Vec<Diagnostic>,
+    /// End of the last sequence warned about, so that `...` is not warned about again as `..`
+    warned: usize,
+    max_depth: Option<usize>,
+    depth: usize,
+    /// Position of the `]` closing each `[`
+    brackets: HashMap<usize, usize>,
+    /// Earliest position from which a delimiter, as its character and length, was searched for
+    /// in vain up to some end: searching from further on up to the same end fails too, which
+    /// keeps unmatched delimiters from making reading quadratic
+    unclosed: HashMap<(char, usize, usize), usize>,
+}
+
+impl Inlines<'_> {
+    fn is_unclosed(&self, delimiter: (char, usize), from: usize, end: usize) -> bool {
+        self.unclosed
+            .get(&(delimiter.0, delimiter.1, end))
+            .is_some_and(|&start| start <= from)
+    }
+
+    fn set_unclosed(&mut self, delimiter: (char, usize), from: usize, end: usize) {
+        let start = self
+            .unclosed
+            .entry((delimiter.0, delimiter.1, end))
+            .or_insert(from);
+        *start = (*start).min(from);
+    }
+
+    fn span(&self, range: Range<usize>) -> Span {
+        let line = self
+            .starts
+            .partition_point(|(start, _)| *start <= range.start);
+        let (start, offset) = line
+            .checked_sub(1)
+            .map(|line| self.starts[line])
+            .unwrap_or_default();
+        Span::new(offset + range.start - start, offset + range.end - start)
+    }
+
+    fn warn(&mut self, message: impl Into<String>, range: Range<usize>) {
+        let span = self.span(range);
+        self.diagnostics.push(Diagnostic::warning(message, span));
+    }
+
+    /// Content of emphasis, a link or the like
+    fn nested(&mut self, range: Range<usize>) -> Vec<OwnedNode> {
+        self.depth += 1;
+        let nodes = self.parse(range);
+        self.depth -= 1;
+        nodes
+    }
+
+    fn parse(&mut self, range: Range<usize>) -> Vec<OwnedNode> {
+        if let Some(max_depth) = self.max_depth.filter(|&max_depth| self.depth > max_depth) {
+            let span = self.span(range);
+            self.diagnostics.push(Diagnostic::error(
+                format!("content nested more than {max_depth} levels deep is left out"),
+                span,
+            ));
+            return vec![];
+        }
+        let text = self.text;
+        let mut nodes = vec![];
+        let mut buffer = String::new();
+        let mut i = range.start;
+        while i < range.end {
+            let rest = &text[i..range.end];
+            let Some(c) = rest.chars().next() else {
+                break;
+            };
+            match c {
+                '\\' => match rest[1..].chars().next() {
+                    Some('\n') => {
+                        flush(&mut buffer, &mut nodes);
+                        nodes.push(Node::NewLine);
+                        i += 2;
+                        continue;
+                    }
+                    Some(escaped) if escaped.is_ascii_punctuation() => {
+                        self.hazard(i + 1, &rest[1..]);
+                        buffer.push(escaped);
+                        i += 2;
+                        continue;
+                    }
+                    _ => (),
+                },
+                '`' => {
+                    if let Some((code, end)) = self.code_span(i, range.end) {
+                        if code.contains('`') {
+                            self.warn(
+                                "code containing a backtick cannot be written in this dialect",
+                                i..end,
+                            );
+                        }
+                        flush(&mut buffer, &mut nodes);
+                        nodes.push(Node::InlineCode(Cow::Owned(code)));
+                        i = end;
+                        continue;
+                    }
+                    // An unmatched run of backticks is literal as a whole
+                    let run = run_length(rest, '`');
+                    self.hazard(i, rest);
+                    buffer.push_str(&rest[..run]);
+                    i += run;
+                    continue;
+                }
+                '*' | '_' => {
+                    if let Some((node, end)) = self.emphasis(i, range.end, c) {
+                        flush(&mut buffer, &mut nodes);
+                        nodes.push(node);
+                        i = end;
+                        continue;
+                    }
+                }
+                '~' if rest.starts_with("~~") => {
+                    if let Some(close) = self.strikethrough(i, range.end) {
+                        flush(&mut buffer, &mut nodes);
+                        nodes.push(Node::Striked(self.nested(i + 2..close)));
+                        i = close + 2;
+                        continue;
+                    }
+                }
+                '!' if rest.starts_with("![") => {
+                    if let Some((description, _, end)) = self.link(i + 1, range.end) {
+                        self.warn(
+                            "images are not supported in this dialect, only their description is kept",
+                            i..end,
+                        );
+                        flush(&mut buffer, &mut nodes);
+                        nodes.extend(self.nested(description));
+                        i = end;
+                        continue;
+                    }
+                }
+                '[' => {
+                    if let Some((label, url, end)) = self.link(i, range.end) {
+                        self.warn(
+                            "links have no syntax in this dialect and are written out as text",
+                            i..end,
+                        );
+                        flush(&mut buffer, &mut nodes);
+                        let childen = self.nested(label);
+                        nodes.push(Node::Link {
+                            url: Cow::Owned(url),
+                            childen,
+                        });
+                        i = end;
+                        continue;
+                    }
+                }
+                '<' => {
+                    if let Some(tag) = rest.find('>').map(|end| &rest[..=end]) {
+                        let inner = &tag[1..tag.len() - 1];
+                        if let Some(url) = autolink(inner) {
+                            self.warn(
+                                "links have no syntax in this dialect and are written out as text",
+                                i..i + tag.len(),
+                            );
+                            flush(&mut buffer, &mut nodes);
+                            nodes.push(Node::Link {
+                                url: Cow::Owned(url),
+                                childen: vec![Node::Text(Cow::Owned(inner.to_string()))],
+                            });
+                            i += tag.len();
+                            continue;
+                        }
+                        if inner.starts_with(|c: char| c.is_ascii_alphabetic() || "/!?".contains(c))
+                        {
+                            self.warn(
+                                "inline HTML is kept as text in this dialect",
+                                i..i + tag.len(),
+                            );
+                            buffer.push_str(tag);
+                            i += tag.len();
+                            continue;
+                        }
+                    }
+                }
+                '&' => {
+                    if let Some((decoded, length)) = entity(rest) {
+                        match decoded {
+                            '\u{a0}' => {
+                                flush(&mut buffer, &mut nodes);
+                                nodes.push(Node::Nbsp);
+                            }
+                            decoded => buffer.push(decoded),
+                        }
+                        i += length;
+                        continue;
+                    }
+                }
+                '\n' => {
+                    let hard = buffer.ends_with("  ");
+                    buffer.truncate(buffer.trim_end_matches([' ', '\t']).len());
+                    if hard {
+                        flush(&mut buffer, &mut nodes);
+                        nodes.push(Node::NewLine);
+                    } else {
+                        buffer.push(' ');
+                    }
+                    i += 1;
+                    continue;
+                }
+                _ => (),
+            }
+            self.hazard(i, rest);
+            buffer.push(c);
+            i += c.len_utf8();
+        }
+        flush(&mut buffer, &mut nodes);
+        nodes
+    }
+
+    /// Warns when the literal text at `position` would be read as markup by this dialect
+    fn hazard(&mut self, position: usize, rest: &str) {
+        const SEQUENCES: [(&str, &str); 16] = [
+            ("\\eqref{", "is a reference"),
+            ("\\ref{", "is a reference"),
+            ("\\[", "opens display math"),
+            ("...", "is a dotted rule"),
+            ("---", "is a dashed rule"),
+            ("===", "is a rule"),
+            ("^^^", "is a sawtooth rule"),
+            ("..", "delimits underlining"),
+            ("||", "delimits highlighting"),
+            ("~~", "delimits strikethrough"),
+            ("%", "opens an environment"),
+            ("$", "delimits math"),
+            ("*", "delimits emphasis"),
+            ("_", "delimits emphasis"),
+            ("~", "is a non-breaking space"),
+            ("`", "delimits code"),
+        ];
+        if position < self.warned {
+            return;
+        }
+        if let Some((sequence, meaning)) = SEQUENCES.iter().find(|(s, _)| rest.starts_with(s)) {
+            self.warned = position + sequence.len();
+            self.warn(
+                format!("`{sequence}` {meaning} in this dialect"),
+                position..position + sequence.len(),
+            );
+        }
+    }
+
+    /// Code span opened by the backticks at `start`, with where it ends
+    fn code_span(&mut self, start: usize, end: usize) -> Option<(String, usize)> {
+        let run = run_length(&self.text[start..end], '`');
+        let mut i = start + run;
+        if self.is_unclosed(('`', run), i, end) {
+            return None;
+        }
+        while let Some(found) = self.text[i..end].find('`') {
+            let close = i + found;
+            let length = run_length(&self.text[close..end], '`');
+            if length == run {
+                let code = self.text[start + run..close].replace('\n', " ");
+                let code = match code.strip_prefix(' ').and_then(|c| c.strip_suffix(' ')) {
+                    Some(stripped) if !code.trim().is_empty() => stripped.to_string(),
+                    _ => code,
+                };
+                return Some((code, close + length));
+            }
+            i = close + length;
+        }
+        self.set_unclosed(('`', run), start + run, end);
+        None
+    }
+
+    /// Emphasis opened by the run of `mark` at `start`, `**` giving bold and `*` italic, with
+    /// where it ends
+    fn emphasis(&mut self, start: usize, end: usize, mark: char) -> Option<(OwnedNode, usize)> {
+        let text = self.text;
+        let run = run_length(&text[start..end], mark);
+        let before = text[..start].chars().next_back();
+        let after = text[start + run..end].chars().next()?;
+        if after.is_whitespace() || (mark == '_' && before.is_some_and(char::is_alphanumeric)) {
+            return None;
+        }
+        let strong = run >= 2;
+        let width = if strong { 2 } else { 1 };
+        let inner = start + width;
+        if self.is_unclosed((mark, width), inner, end) {
+            return None;
+        }
+        let mut i = inner;
+        while i < end {
+            let rest = &text[i..end];
+            let c = rest.chars().next()?;
+            match c {
+                '\\' => i += 1 + rest[1..].chars().next().map_or(0, char::len_utf8),
+                '`' => {
+                    i = match self.code_span(i, end) {
+                        Some((_, after)) => after,
+                        None => i + run_length(rest, '`'),
+                    }
+                }
+                c if c == mark => {
+                    let length = run_length(rest, mark);
+                    let previous = text[..i].chars().next_back();
+                    let next = text[i + length..end].chars().next();
+                    let closes = i > inner
+                        && !previous.is_some_and(char::is_whitespace)
+                        && !(mark == '_' && next.is_some_and(char::is_alphanumeric));
+                    if closes && strong && length >= 2 {
+                        // The run closing `***a***` is split as `*` then `**`
+                        let close = i + length - 2;
+                        return Some((Node::Bold(self.nested(inner..close)), close + 2));
+                    }
+                    if closes && !strong && length == 1 {
+                        return Some((Node::Italic(self.nested(inner..i)), i + 1));
+                    }
+                    i += length;
+                }
+                c => i += c.len_utf8(),
+            }
+        }
+        self.set_unclosed((mark, width), inner, end);
+        None
+    }
+
+    /// Start of the `~~` closing the one at `start`
+    fn strikethrough(&mut self, start: usize, end: usize) -> Option<usize> {
+        let first = self.text[start + 2..end].chars().next()?;
+        if first.is_whitespace() || self.is_unclosed(('~', 2), start + 2, end) {
+            return None;
+        }
+        let mut i = start + 2 + first.len_utf8();
+        while let Some(found) = self.text[i..end].find("~~") {
+            let close = i + found;
+            if !self.text[..close].ends_with(char::is_whitespace) {
+                return Some(close);
+            }
+            i = close + 2;
+        }
+        self.set_unclosed(('~', 2), start + 2, end);
+        None
+    }
+
+    /// Inline link whose text opens with the `[` at `start`: the range of its text, its
+    /// destination and where it ends
+    fn link(&self, start: usize, end: usize) -> Option<(Range<usize>, String, usize)> {
+        let bytes = &self.text.as_bytes()[..end];
+        let close = *self.brackets.get(&start).filter(|&&close| close < end)?;
+        let mut i = close + 1;
+        if bytes.get(i) != Some(&b'(') {
+            return None;
+        }
+        i += 1;
+        let skip_whitespace = |mut i: usize| {
+            while bytes.get(i).is_some_and(u8::is_ascii_whitespace) {
+                i += 1;
+            }
+            i
+        };
+        i = skip_whitespace(i);
+        let url = if bytes.get(i) == Some(&b'<') {
+            let length = self.text[i + 1..end].find(['>', '\n'])?;
+            if bytes[i + 1 + length] != b'>' {
+                return None;
+            }
+            let url = &self.text[i + 1..i + 1 + length];
+            i += length + 2;
+            url
+        } else {
+            let from = i;
+            let mut parens = 0;
+            while let Some(&byte) = bytes.get(i) {
+                match byte {
+                    b'(' => parens += 1,
+                    b')' if parens == 0 => break,
+                    b')' => parens -= 1,
+                    byte if byte.is_ascii_whitespace() => break,
+                    _ => (),
+                }
+                i += 1;
+            }
+            &self.text[from..i]
+        };
+        i = skip_whitespace(i);
+        if let Some(&quote) = bytes.get(i).filter(|byte| b"\"'(".contains(byte)) {
+            let closing = if quote == b'(' { b')' } else { quote };
+            i += 1;
+            while *bytes.get(i)? != closing {
+                i += 1;
+            }
+            i = skip_whitespace(i + 1);
+        }
+        if bytes.get(i) != Some(&b')') {
+            return None;
+        }
+        Some((start + 1..close, url.to_string(), i + 1))
+    }
+}
+
+/// Position of the `]` closing each `[` of `text`, backslash escapes aside
+fn matching_brackets(text: &str) -> HashMap<usize, usize> {
+    let bytes = text.as_bytes();
+    let mut brackets = HashMap::new();
+    let mut open = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'[' => open.push(i),
+            b']' => {
+                if let Some(start) = open.pop() {
+                    brackets.insert(start, i);
+                }
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    brackets
+}
+
+fn flush(buffer: &mut String, nodes: &mut Vec<OwnedNode>) {
+    if !buffer.is_empty() {
+        nodes.push(Node::Text(Cow::Owned(std::mem::take(buffer))));
+    }
+}
+
+/// Columns of leading whitespace, a tab reaching the next multiple of four
+fn indent(text: &str) -> usize {
+    let mut column = 0;
+    for c in text.chars() {
+        match c {
+            ' ' => column += 1,
+            '\t' => column += 4 - column % 4,
+            _ => break,
+        }
+    }
+    column
+}
+
+fn run_length(text: &str, c: char) -> usize {
+    text.len() - text.trim_start_matches(c).len()
+}
+
+/// Character and length of the fence opening a code block
+fn fence(text: &str) -> Option<(char, usize)> {
+    let mark = text.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let width = run_length(text, mark);
+    let info_ok = mark == '~' || !text[width..].contains('`');
+    (width >= 3 && info_ok).then_some((mark, width))
+}
+
+/// Level and content of an ATX heading, its closing `#`s removed
+fn atx(line: Line) -> Option<(u8, Line)> {
+    let level = run_length(line.text, '#');
+    let rest = &line.text[level..];
+    if !(1..=6).contains(&level) || !(rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        return None;
+    }
+    let content = line.skip(level).trim_start();
+    let mut text = content.text.trim_end();
+    let unclosed = text.trim_end_matches('#');
+    if unclosed.is_empty() || unclosed.ends_with([' ', '\t']) {
+        text = unclosed.trim_end();
+    }
+    Some((level as u8, Line { text, ..content }))
+}
+
+/// Level of the heading underlined by `text`, if it is a setext underline
+fn setext(text: &str) -> Option<u8> {
+    let text = text.trim_end();
+    match text.chars().next()? {
+        '=' if text.chars().all(|c| c == '=') => Some(1),
+        '-' if text.chars().all(|c| c == '-') => Some(2),
+        _ => None,
+    }
+}
+
+fn thematic_break(text: &str) -> bool {
+    let Some(mark) = text.chars().next().filter(|c| matches!(c, '-' | '*' | '_')) else {
+        return false;
+    };
+    text.chars().all(|c| c == mark || c == ' ' || c == '\t')
+        && text.chars().filter(|&c| c == mark).count() >= 3
+}
+
+fn html_start(text: &str) -> bool {
+    text.strip_prefix('<').is_some_and(|rest| {
+        rest.starts_with(|c: char| c.is_ascii_alphabetic() || "/!?".contains(c))
+    })
+}
+
+/// Lines that open a block of their own rather than continue a paragraph lazily
+fn is_block_start(line: Line) -> bool {
+    let text = line.trim_start().text;
+    line.indent() < 4
+        && (fence(text).is_some()
+            || atx(line.trim_start()).is_some()
+            || thematic_break(text)
+            || text.starts_with('>')
+            || list_marker(text).is_some_and(|marker| !marker.blank)
+            || html_start(text))
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Marker {
+    /// Bullet character, or delimiter following the number of an ordered item
+    kind: char,
+    ordered: bool,
+    start: u64,
+    /// Bytes taken by the marker
+    width: usize,
+    /// Nothing follows the marker on its line
+    blank: bool,
+}
+
+fn list_marker(text: &str) -> Option<Marker> {
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (kind, ordered, start, width) = match text.chars().next()? {
+        c @ ('-' | '*' | '+') => (c, false, 0, 1),
+        _ if (1..=9).contains(&digits) => {
+            let kind = text[digits..]
+                .chars()
+                .next()
+                .filter(|c| matches!(c, '.' | ')'))?;
+            (kind, true, text[..digits].parse().ok()?, digits + 1)
+        }
+        _ => return None,
+    };
+    let rest = &text[width..];
+    if !(rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        return None;
+    }
+    Some(Marker {
+        kind,
+        ordered,
+        start,
+        width,
+        blank: rest.trim().is_empty(),
+    })
+}
+
+/// Destination of an autolink, `<` and `>` left out
+fn autolink(inner: &str) -> Option<String> {
+    if inner.is_empty() || inner.contains(|c: char| c.is_whitespace() || c == '<') {
+        return None;
+    }
+    match inner.split_once(':') {
+        Some((scheme, _))
+            if scheme.len() >= 2
+                && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+.-".contains(c)) =>
+        {
+            Some(inner.to_string())
+        }
+        None if inner.contains('@') && !inner.starts_with('@') && !inner.ends_with('@') => {
+            Some(format!("mailto:{inner}"))
+        }
+        _ => None,
+    }
+}
+
+/// Character written by the entity opening `text`, with the entity's length
+fn entity(text: &str) -> Option<(char, usize)> {
+    let (end, _) = text.char_indices().take(12).find(|&(_, c)| c == ';')?;
+    let name = &text[1..end];
+    let decoded = match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "copy" => '©',
+        "mdash" => '—',
+        "ndash" => '–',
+        "hellip" => '…',
+        _ => {
+            let number = name.strip_prefix('#')?;
+            let code = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            char::from_u32(code).filter(|&c| c != '\0')?
+        }
+    };
+    Some((decoded, end + 1))
+}
@@ -0,0 +1,121 @@
+use crate::diagnostics::Diagnostic;
+use crate::document::Metadata;
+use crate::headings;
+use crate::parser::Node;
+use crate::{parse_nodes, ParseOptions};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File spliced into the document by `%include`, kept for the spans pointing into it
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    /// As written in the directive, joined to the including file's directory
+    pub path: PathBuf,
+    pub source: String,
+}
+
+struct Context<'o> {
+    options: &'o ParseOptions,
+    files: &'o mut Vec<SourceFile>,
+    diagnostics: &'o mut Vec<Diagnostic>,
+    /// Files being included, outermost first, to catch cycles
+    stack: Vec<PathBuf>,
+}
+
+/// Replaces every `%include` with the content of the file it names, recursively
+///
+/// Paths are relative to the directory of the including file, `options.path` for the input
+/// itself. Included files are added to `files` and their nodes' spans point into them. Unreadable
+/// files, files including themselves and any file in `ParseOptions::safe` mode are reported and
+/// left out.
+pub fn expand(
+    nodes: &mut Vec<Node>,
+    options: &ParseOptions,
+    files: &mut Vec<SourceFile>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let input = options.path.as_deref();
+    let dir = input.and_then(Path::parent).unwrap_or(Path::new(""));
+    let mut context = Context {
+        options,
+        files,
+        diagnostics,
+        stack: input
+            .and_then(|path| fs::canonicalize(path).ok())
+            .into_iter()
+            .collect(),
+    };
+    splice(nodes, dir, &mut context);
+}
+
+fn splice(nodes: &mut Vec<Node>, dir: &Path, context: &mut Context) {
+    let mut index = 0;
+    while index < nodes.len() {
+        if let Node::Include { path, offset, span } = &nodes[index] {
+            let (path, offset, span) = (path.to_string(), *offset, *span);
+            let included = load(&path, offset, dir, context).unwrap_or_else(|message| {
+                context.diagnostics.push(Diagnostic::error(message, span));
+                vec![]
+            });
+            let count = included.len();
+            nodes.splice(index..=index, included);
+            index += count;
+        } else {
+            if let Some(children) = nodes[index].children_mut() {
+                splice(children, dir, context);
+            }
+            index += 1;
+        }
+    }
+}
+
+fn load(
+    path: &str,
+    offset: i64,
+    dir: &Path,
+    context: &mut Context,
+) -> Result<Vec<Node<'static>>, String> {
+    if context.options.safe.is_some() {
+        return Err(format!("cannot include `{path}` from untrusted input"));
+    }
+    let full_path = dir.join(path);
+    let canonical = fs::canonicalize(&full_path)
+        .map_err(|error| format!("cannot include `{path}`: {error}"))?;
+    if context.stack.contains(&canonical) {
+        return Err(format!(
+            "`{path}` includes itself, directly or through other files"
+        ));
+    }
+    let source = fs::read_to_string(&canonical)
+        .map_err(|error| format!("cannot include `{path}`: {error}"))?;
+
+    let file = context.files.len() + 1;
+    context.files.push(SourceFile {
+        path: full_path.clone(),
+        source: String::new(),
+    });
+    // The included file's front matter is ignored
+    let (_, body) = Metadata::parse(&source);
+    let (mut nodes, diagnostics) =
+        parse_nodes(body, source.len() - body.len(), file, context.options);
+    context.diagnostics.extend(diagnostics);
+
+    context.stack.push(canonical);
+    splice(
+        &mut nodes,
+        full_path.parent().unwrap_or(Path::new("")),
+        context,
+    );
+    context.stack.pop();
+
+    if offset != 0 {
+        headings::walk(&mut nodes, &mut |node| {
+            if let Node::Header { level, .. } = node {
+                *level = (*level as i64 + offset).clamp(1, 6) as u8;
+            }
+        });
+    }
+    let nodes = nodes.into_iter().map(Node::into_owned).collect();
+    context.files[file - 1].source = source;
+    Ok(nodes)
+}
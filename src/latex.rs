@@ -0,0 +1,503 @@
+use crate::document::Document;
+use crate::environments::{EnvStyle, EnvType};
+use crate::html::{self, Options, SolutionMode};
+use crate::lexer::{HrStyle, Span};
+use crate::parser::{Label, ListType, Node};
+use crate::references::Target;
+use crate::render::{self, environment_id, is_concealed, Renderer};
+use crate::toc::TocEntry;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Packages used by the markup of every hook
+const PACKAGES: &str = "\\usepackage[utf8]{inputenc}
+\\usepackage[T1]{fontenc}
+\\usepackage{amsmath,amssymb,amsthm}
+\\usepackage{xcolor,soul}
+\\usepackage[normalem]{ulem}
+\\usepackage{dashrule}
+\\usepackage{hyperref}
+";
+
+/// The LaTeX backend, mapping environments onto amsthm theorems
+#[derive(Debug, Clone, Default)]
+pub struct LatexRenderer {
+    options: Options,
+    /// Inside a paragraph, the only place a line can be broken
+    in_paragraph: bool,
+    /// Deepest header level whose number theorems or equations carry, which LaTeX can only
+    /// count if these headers are numbered
+    numbered_levels: usize,
+}
+
+impl LatexRenderer {
+    pub fn new(options: Options) -> Self {
+        Self {
+            options,
+            in_paragraph: false,
+            numbered_levels: 0,
+        }
+    }
+
+    fn lang(&self) -> &str {
+        self.options.lang.as_deref().unwrap_or("en")
+    }
+
+    /// Renders an environment, citing the statement it is attached to when `link_statement` is
+    /// set or when it is given with `of`
+    fn environment_block(&mut self, node: &Node, link_statement: bool) -> String {
+        let Node::Env {
+            environment_type,
+            environment_arg,
+            params,
+            attached,
+            children,
+            ..
+        } = node
+        else {
+            return self.node(node);
+        };
+        if environment_type.conceal
+            && is_concealed(params, &self.options)
+            && self.options.conceal == html::ConcealMode::Strip
+        {
+            return String::new();
+        }
+
+        let mut details = vec![];
+        if let Some(statement) = attached
+            .as_ref()
+            .filter(|_| link_statement || params.text("of").is_some())
+        {
+            details.push(self.statement_name(statement));
+        }
+        if let Some(arg) = environment_arg {
+            details.push(self.nodes(arg));
+        }
+        let details = details.join(", ");
+        let label = match environment_id(node) {
+            Some(id) => format!("\\label{{{id}}}"),
+            None => String::new(),
+        };
+        let children = self.nodes(children);
+        let name = environment_type.label_for(self.lang()).map(escape);
+
+        match name {
+            // amsthm's own environment, which ends with its QED mark
+            Some(name) if environment_type.qed.is_some() => {
+                let title = if details.is_empty() {
+                    name
+                } else {
+                    format!("{name} ({details})")
+                };
+                format!("\\begin{{proof}}[{{{title}}}]{label}\n{children}\\end{{proof}}\n")
+            }
+            Some(_) => {
+                let details = if details.is_empty() {
+                    details
+                } else {
+                    format!("[{{{details}}}]")
+                };
+                format!(
+                    "\\begin{{{name}}}{details}{label}\n{children}\\end{{{name}}}\n",
+                    name = environment_type.name
+                )
+            }
+            // Folding and concealing blocks, which paper cannot do
+            None => children,
+        }
+    }
+
+    /// "Théorème~\ref{id}", or just the reference for sections
+    fn statement_name(&self, target: &Target) -> String {
+        let reference = format!("\\ref{{{}}}", target.id);
+        match target
+            .environment_type
+            .as_ref()
+            .and_then(|environment_type| environment_type.label_for(self.lang()))
+        {
+            Some(label) => format!("{}~{reference}", escape(label)),
+            None => reference,
+        }
+    }
+}
+
+impl Renderer for LatexRenderer {
+    fn options(&self) -> &Options {
+        &self.options
+    }
+
+    fn document(&mut self, document: &Document) -> String {
+        self.numbered_levels = numbered_levels(&document.nodes);
+        let body = match self.options.solutions {
+            SolutionMode::Collect => render::chapters(self, &document.nodes),
+            _ => self.nodes(&document.nodes),
+        };
+        if !self.options.standalone {
+            return body;
+        }
+
+        let metadata = &document.metadata;
+        let mut preamble = String::from("\\documentclass{article}\n");
+        preamble.push_str(PACKAGES);
+        if let Some(language) = metadata.lang().and_then(babel_language) {
+            preamble.push_str(&format!("\\usepackage[{language}]{{babel}}\n"));
+        }
+        preamble.push_str(&theorems(&document.nodes, self.lang()));
+        if let Some(within) = equations_within(&document.nodes) {
+            preamble.push_str(&format!("\\numberwithin{{equation}}{{{within}}}\n"));
+        }
+        let mut title = String::new();
+        if let Some(text) = metadata.title() {
+            preamble.push_str(&format!("\\title{{{}}}\n", escape(text)));
+            if let Some(author) = metadata.get("author") {
+                preamble.push_str(&format!("\\author{{{}}}\n", escape(author)));
+            }
+            preamble.push_str("\\date{}\n");
+            title.push_str("\\maketitle\n\n");
+        }
+        format!("{preamble}\n\\begin{{document}}\n\n{title}{body}\\end{{document}}\n")
+    }
+
+    fn header(
+        &mut self,
+        level: u8,
+        id: Option<&str>,
+        _number: &[u32],
+        children: &[Node],
+    ) -> String {
+        let star = if self.options.number_sections || usize::from(level) <= self.numbered_levels {
+            ""
+        } else {
+            "*"
+        };
+        let label = match id {
+            Some(id) => format!("\\label{{{id}}}"),
+            None => String::new(),
+        };
+        format!(
+            "\n\\{}{star}{{{}}}{label}\n\n",
+            sectioning(level),
+            self.nodes(children)
+        )
+    }
+
+    fn bold(&mut self, children: &[Node]) -> String {
+        format!("\\textbf{{{}}}", self.nodes(children))
+    }
+
+    fn italic(&mut self, children: &[Node]) -> String {
+        format!("\\emph{{{}}}", self.nodes(children))
+    }
+
+    fn striked(&mut self, children: &[Node]) -> String {
+        format!("\\sout{{{}}}", self.nodes(children))
+    }
+
+    fn underline(&mut self, children: &[Node], _span: Span) -> String {
+        format!("\\uline{{{}}}", self.nodes(children))
+    }
+
+    fn highlighted(&mut self, children: &[Node], _span: Span) -> String {
+        format!("\\hl{{{}}}", self.nodes(children))
+    }
+
+    fn link(&mut self, url: &str, children: &[Node]) -> String {
+        if self.options.safe && !html::is_safe_url(url) {
+            return self.nodes(children);
+        }
+        format!("\\href{{{}}}{{{}}}", escape_url(url), self.nodes(children))
+    }
+
+    fn list(&mut self, list_type: &ListType, children: &[Node]) -> String {
+        let environment = match list_type {
+            ListType::Normal => "itemize",
+        };
+        format!(
+            "\\begin{{{environment}}}\n{}\\end{{{environment}}}\n\n",
+            self.nodes(children)
+        )
+    }
+
+    fn list_item(&mut self, children: &[Node]) -> String {
+        format!("\\item {}\n", self.nodes(children).trim_end())
+    }
+
+    fn inline_code(&mut self, code: &str) -> String {
+        format!("\\texttt{{{}}}", escape(code))
+    }
+
+    fn code_block(&mut self, _language: Option<&str>, code: &str) -> String {
+        let newline = if code.ends_with('\n') { "" } else { "\n" };
+        format!("\\begin{{verbatim}}{code}{newline}\\end{{verbatim}}\n\n")
+    }
+
+    fn raw_html(&mut self, _html: &str) -> String {
+        String::new()
+    }
+
+    fn inline_math(&mut self, math: &str, _span: Span) -> String {
+        format!("${math}$")
+    }
+
+    fn display_math(
+        &mut self,
+        math: &str,
+        _span: Span,
+        label: Option<&Label>,
+        number: &[u32],
+    ) -> String {
+        match label {
+            Some(label) if !number.is_empty() => format!(
+                "\\begin{{equation}}\\label{{{}}}{math}\\end{{equation}}\n",
+                label.name
+            ),
+            _ => format!("\\[{math}\\]\n"),
+        }
+    }
+
+    fn environment(&mut self, node: &Node) -> String {
+        match node {
+            Node::Env {
+                environment_type, ..
+            } if environment_type.solution && self.options.solutions != SolutionMode::Inline => {
+                match (self.options.solutions, environment_id(node)) {
+                    (SolutionMode::Collect, Some(id)) => format!(
+                        "\\begin{{flushright}}\\hyperref[{id}]{{{}}}\\end{{flushright}}\n",
+                        escape(
+                            environment_type
+                                .label_for(self.lang())
+                                .unwrap_or(&environment_type.name)
+                        )
+                    ),
+                    _ => String::new(),
+                }
+            }
+            _ => self.environment_block(node, false),
+        }
+    }
+
+    /// amsthm's `proof` environment adds the mark itself
+    fn nodes_with_qed(&mut self, nodes: &[Node], _mark: &str) -> String {
+        self.nodes(nodes)
+    }
+
+    fn solutions(&mut self, solutions: &[&Node], level: u8) -> String {
+        let mut out = format!("\\{}*{{Solutions}}\n\n", sectioning(level));
+        for solution in solutions {
+            out.push_str(&self.environment_block(solution, true));
+        }
+        out
+    }
+
+    fn new_line(&mut self) -> String {
+        if self.in_paragraph {
+            "\\\\\n".to_string()
+        } else {
+            "\n".to_string()
+        }
+    }
+
+    fn paragraph(&mut self, children: &[Node]) -> String {
+        let start = children
+            .iter()
+            .position(|node| !matches!(node, Node::NewLine))
+            .unwrap_or(children.len());
+        let end = children
+            .iter()
+            .rposition(|node| !matches!(node, Node::NewLine))
+            .map_or(start, |last| last + 1);
+        let in_paragraph = std::mem::replace(&mut self.in_paragraph, true);
+        let text = self.nodes(&children[start..end]);
+        self.in_paragraph = in_paragraph;
+        format!("{text}\n\n")
+    }
+
+    fn text(&mut self, text: &str) -> String {
+        escape(text)
+    }
+
+    fn hr(&mut self, style: &HrStyle, _span: Span) -> String {
+        let rule = match style {
+            HrStyle::Normal => "\\rule{\\linewidth}{0.4pt}",
+            HrStyle::Dashed => "\\hdashrule{\\linewidth}{0.4pt}{3pt}",
+            HrStyle::Dotted => "\\hdashrule{\\linewidth}{0.4pt}{0.4pt 2pt}",
+            HrStyle::Sawtooth => "\\leaders\\hbox{$\\scriptstyle\\vee$}\\hfill\\kern0pt",
+        };
+        format!("\\par\\noindent{rule}\\par\n\n")
+    }
+
+    fn nbsp(&mut self) -> String {
+        "~".to_string()
+    }
+
+    fn table_of_contents(&mut self, _entries: &[TocEntry]) -> String {
+        "\\tableofcontents\n\n".to_string()
+    }
+
+    fn reference(&mut self, _label: &str, equation: bool, target: Option<&Target>) -> String {
+        match target {
+            Some(target) if equation => format!("\\eqref{{{}}}", target.id),
+            Some(target) => format!("\\ref{{{}}}", target.id),
+            None => "\\textbf{??}".to_string(),
+        }
+    }
+}
+
+/// Renders a document to a standalone `.tex` file or, without `options.standalone`, to the body
+/// of one
+pub fn render(document: &Document, options: &Options) -> String {
+    let options = Options {
+        lang: document
+            .metadata
+            .lang()
+            .map(str::to_string)
+            .or_else(|| options.lang.clone()),
+        ..options.clone()
+    };
+    LatexRenderer::new(options).document(document)
+}
+
+/// `\newtheorem` declarations for the environment types used in `nodes`, grouped by style
+///
+/// Types sharing a counter share the number of the first one declared, types without one are
+/// unnumbered and types without a label are not theorems at all. Numbers restart with the
+/// enclosing section when the document's numbers carry its number.
+fn theorems(nodes: &[Node], lang: &str) -> String {
+    fn collect(nodes: &[Node], types: &mut Vec<Arc<EnvType>>, within: &mut HashMap<String, usize>) {
+        for node in nodes {
+            if let Node::Env {
+                environment_type,
+                number,
+                ..
+            } = node
+            {
+                if !types.iter().any(|t| t.name == environment_type.name) {
+                    types.push(environment_type.clone());
+                }
+                if let Some(counter) = &environment_type.counter {
+                    let depth = within.entry(counter.clone()).or_default();
+                    *depth = (*depth).max(number.len().saturating_sub(1));
+                }
+            }
+            collect(node.children(), types, within);
+        }
+    }
+
+    let mut types = vec![];
+    let mut within = HashMap::new();
+    collect(nodes, &mut types, &mut within);
+
+    let mut declarations = String::new();
+    let mut owners: HashMap<&str, &str> = HashMap::new();
+    for style in [EnvStyle::Plain, EnvStyle::Definition, EnvStyle::Remark] {
+        let mut style_declared = false;
+        for environment_type in types.iter().filter(|t| t.style == style && t.qed.is_none()) {
+            let Some(label) = environment_type.label_for(lang) else {
+                continue;
+            };
+            if !style_declared {
+                declarations.push_str(&format!("\\theoremstyle{{{}}}\n", style.name()));
+                style_declared = true;
+            }
+            let name = &environment_type.name;
+            let label = escape(label);
+            declarations.push_str(&match &environment_type.counter {
+                None => format!("\\newtheorem*{{{name}}}{{{label}}}\n"),
+                Some(counter) => match owners.get(counter.as_str()) {
+                    Some(owner) => format!("\\newtheorem{{{name}}}[{owner}]{{{label}}}\n"),
+                    None => {
+                        owners.insert(counter, name);
+                        let parent = match within.get(counter).copied().unwrap_or(0) {
+                            0 => String::new(),
+                            depth => format!("[{}]", sectioning(depth as u8)),
+                        };
+                        format!("\\newtheorem{{{name}}}{{{label}}}{parent}\n")
+                    }
+                },
+            });
+        }
+    }
+    declarations
+}
+
+/// Number of header levels the numbers of environments and equations carry
+fn numbered_levels(nodes: &[Node]) -> usize {
+    nodes
+        .iter()
+        .map(|node| {
+            let own = match node {
+                Node::Env { number, .. } | Node::DisplayMath { number, .. } => {
+                    number.len().saturating_sub(1)
+                }
+                _ => 0,
+            };
+            own.max(numbered_levels(node.children()))
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Section the equation numbers restart with, if they carry one
+fn equations_within(nodes: &[Node]) -> Option<&'static str> {
+    nodes.iter().find_map(|node| match node {
+        Node::DisplayMath { number, .. } if number.len() > 1 => {
+            Some(sectioning(number.len() as u8 - 1))
+        }
+        _ => equations_within(node.children()),
+    })
+}
+
+/// Sectioning command for headers of `level`, which LaTeX has five of
+fn sectioning(level: u8) -> &'static str {
+    match level {
+        0 | 1 => "section",
+        2 => "subsection",
+        3 => "subsubsection",
+        4 => "paragraph",
+        _ => "subparagraph",
+    }
+}
+
+/// Babel name of the languages given in front matter
+fn babel_language(lang: &str) -> Option<&'static str> {
+    match lang.split(['-', '_']).next()? {
+        "en" => Some("english"),
+        "fr" => Some("french"),
+        "de" => Some("ngerman"),
+        "es" => Some("spanish"),
+        "it" => Some("italian"),
+        "pt" => Some("portuguese"),
+        _ => None,
+    }
+}
+
+/// `text` with LaTeX's special characters typeset literally
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            '#' | '$' | '%' | '&' | '_' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// `url` as the argument of `\href`, where only these characters are special
+fn escape_url(url: &str) -> String {
+    let mut escaped = String::with_capacity(url.len());
+    for c in url.chars() {
+        if matches!(c, '\\' | '#' | '%' | '{' | '}') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -1,3 +1,6 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum HrStyle {
     Normal,
@@ -7,7 +10,7 @@ pub enum HrStyle {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub enum Token {
+pub enum Token<'a> {
     Header(u8),
     Bold,
     Italic,
@@ -16,22 +19,71 @@ pub enum Token {
     Highlighted,
     LinkStart,
     LinkEnd,
-    Text(String),
+    Text(&'a str),
     ListItem(u8),
     Indent(u8),
-    InlineCode(String),
-    CodeBlock(String),
-    InlineMath(String),
-    DisplayMath(String),
-    EnvBegin(String),
-    EnvEnd,
+    InlineCode(&'a str),
+    CodeBlock(Cow<'a, str>),
+    InlineMath(&'a str),
+    DisplayMath(&'a str),
+    /// `%name`, `%%name`… with the number of `%`
+    EnvBegin(u8, &'a str),
+    /// `\ref{label}`
+    Ref(&'a str),
+    /// `\eqref{label}`
+    EqRef(&'a str),
+    /// Line made of `%`, closing the environment opened with as many
+    EnvEnd(u8),
     NewLine,
     Hr(HrStyle),
-    NBSP,
+    Nbsp,
+}
+
+/// Byte range in the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    /// Source the range is in: 0 for the parsed input, `n` for `Document::files[n - 1]`
+    pub file: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span {
+            start,
+            end,
+            file: 0,
+        }
+    }
+
+    /// Same range in a source whose first `offset` bytes were cut off before lexing
+    pub fn shifted(self, offset: usize) -> Self {
+        Span {
+            start: self.start + offset,
+            end: self.end + offset,
+            ..self
+        }
+    }
+
+    /// Same range in another source
+    pub fn in_file(self, file: usize) -> Self {
+        Span { file, ..self }
+    }
+
+    /// Part of this range, given relative to its start
+    pub fn within(self, range: Range<usize>) -> Self {
+        Span {
+            start: self.start + range.start,
+            end: self.start + range.end,
+            ..self
+        }
+    }
 }
 
 pub struct Lexer<'a> {
     input: &'a str,
+    /// Byte offset into `input`
     pos: usize,
 }
 
@@ -40,42 +92,58 @@ impl<'a> Lexer<'a> {
         Lexer { input, pos: 0 }
     }
 
-    fn remove_indents(text: String, _level: u8) -> String {
+    fn remove_indents(text: Cow<'a, str>, _level: u8) -> Cow<'a, str> {
         //TODO: Remove indent in code blocks
         text
     }
 
-    pub fn tokenize(&mut self) -> Vec<Token> {
+    pub fn tokenize(&mut self) -> Vec<Token<'a>> {
+        self.tokenize_with_spans().0
+    }
+
+    /// Tokens along with the byte range each one was read from
+    pub fn tokenize_with_spans(&mut self) -> (Vec<Token<'a>>, Vec<Span>) {
         let mut tokens: Vec<Token> = vec![];
+        let mut spans: Vec<Span> = vec![];
 
-        let mut current_text: Vec<char> = vec![];
-        fn push_text(tokens: &mut Vec<Token>, current_text: &mut Vec<char>) {
-            if !current_text.is_empty() {
-                tokens.push(Token::Text(current_text.iter().collect::<String>()));
-                current_text.clear();
+        // Pending text, as a byte range of the input
+        let mut current_text: Range<usize> = 0..0;
+        fn push_text<'a>(
+            tokens: &mut Vec<Token<'a>>,
+            spans: &mut Vec<Span>,
+            input: &'a str,
+            current_text: &mut Range<usize>,
+        ) {
+            if current_text.start < current_text.end {
+                tokens.push(Token::Text(&input[current_text.clone()]));
+                spans.push(Span::new(current_text.start, current_text.end));
+                current_text.start = current_text.end;
             }
         }
 
         let mut line_begins = true;
 
-        while let Some(current) = &self.advance() {
+        // Tokens pushed by an iteration, besides text, span everything it consumed
+        let mut start = self.pos;
+        loop {
+            spans.resize(tokens.len(), Span::new(start, self.pos));
+            start = self.pos;
+            let Some(current) = self.advance() else {
+                break;
+            };
             match current {
-                ' ' => {
-                    if line_begins {
-                        let mut indent_level: u8 = 1;
-                        while !self.eof() && self.next_is(' ') {
-                            indent_level += 1;
-                            self.advance();
-                        }
-                        if self.next_is('-') {
-                            tokens.push(Token::ListItem(indent_level));
-                            self.advance(); // '-'
-                            self.advance(); // ' '
-                        } else {
-                            tokens.push(Token::Indent(indent_level));
-                        }
+                ' ' if line_begins => {
+                    let mut indent_level: u8 = 1;
+                    while !self.eof() && self.next_is(' ') {
+                        indent_level = indent_level.saturating_add(1);
+                        self.advance();
+                    }
+                    if self.next_is('-') {
+                        tokens.push(Token::ListItem(indent_level));
+                        self.advance(); // '-'
+                        self.advance(); // ' '
                     } else {
-                        current_text.push(*current);
+                        tokens.push(Token::Indent(indent_level));
                     }
                 }
                 '-' if line_begins && self.next_is(' ') => {
@@ -83,39 +151,44 @@ impl<'a> Lexer<'a> {
                     self.advance();
                 }
                 // Hr
-                '=' if self.next_are(vec!['='; 2]) => {
+                '=' if self.next_are("==") => {
+                    push_text(&mut tokens, &mut spans, self.input, &mut current_text);
                     self.advance();
                     self.advance();
                     tokens.push(Token::Hr(HrStyle::Normal));
                 }
-                '-' if self.next_are(vec!['-'; 2]) => {
+                '-' if self.next_are("--") => {
+                    push_text(&mut tokens, &mut spans, self.input, &mut current_text);
                     self.advance();
                     self.advance();
                     tokens.push(Token::Hr(HrStyle::Dashed));
                 }
-                '.' if self.next_are(vec!['.'; 2]) => {
+                '.' if self.next_are("..") => {
+                    push_text(&mut tokens, &mut spans, self.input, &mut current_text);
                     self.advance();
                     self.advance();
                     tokens.push(Token::Hr(HrStyle::Dotted));
                 }
-                '^' if self.next_are(vec!['^'; 2]) => {
+                '^' if self.next_are("^^") => {
+                    push_text(&mut tokens, &mut spans, self.input, &mut current_text);
                     self.advance();
                     self.advance();
                     tokens.push(Token::Hr(HrStyle::Sawtooth));
                 }
 
                 '\n' => {
-                    push_text(&mut tokens, &mut current_text);
+                    push_text(&mut tokens, &mut spans, self.input, &mut current_text);
                     tokens.push(Token::NewLine);
                     line_begins = true;
                     continue;
                 }
                 '#' if line_begins => {
-                    push_text(&mut tokens, &mut current_text);
-                    tokens.push(Token::Header(self.advance_until(' ').len() as u8 + 1));
+                    push_text(&mut tokens, &mut spans, self.input, &mut current_text);
+                    let level = self.advance_until(' ').len() + 1;
+                    tokens.push(Token::Header(u8::try_from(level).unwrap_or(u8::MAX)));
                 }
                 '*' => {
-                    push_text(&mut tokens, &mut current_text);
+                    push_text(&mut tokens, &mut spans, self.input, &mut current_text);
                     if self.next_is('*') {
                         self.advance();
                         tokens.push(Token::Bold);
@@ -124,7 +197,7 @@ impl<'a> Lexer<'a> {
                     }
                 }
                 '_' => {
-                    push_text(&mut tokens, &mut current_text);
+                    push_text(&mut tokens, &mut spans, self.input, &mut current_text);
                     if self.next_is('_') {
                         self.advance();
                         tokens.push(Token::Bold);
@@ -133,112 +206,106 @@ impl<'a> Lexer<'a> {
                     }
                 }
                 '~' => {
-                    push_text(&mut tokens, &mut current_text);
+                    push_text(&mut tokens, &mut spans, self.input, &mut current_text);
                     if self.next_is('~') {
                         self.advance();
                         tokens.push(Token::Striked);
                     } else {
-                        tokens.push(Token::NBSP);
+                        tokens.push(Token::Nbsp);
                     }
                 }
-                '.' => {
-                    push_text(&mut tokens, &mut current_text);
-                    if self.next_is('.') {
-                        self.advance();
-                        tokens.push(Token::Underline);
-                    } else {
-                        current_text.push(*current);
-                    }
+                '.' if self.next_is('.') => {
+                    push_text(&mut tokens, &mut spans, self.input, &mut current_text);
+                    self.advance();
+                    tokens.push(Token::Underline);
                 }
-                '|' => {
-                    push_text(&mut tokens, &mut current_text);
-                    if self.next_is('|') {
-                        self.advance();
-                        tokens.push(Token::Highlighted);
-                    } else {
-                        current_text.push(*current);
-                    }
+                '|' if self.next_is('|') => {
+                    push_text(&mut tokens, &mut spans, self.input, &mut current_text);
+                    self.advance();
+                    tokens.push(Token::Highlighted);
                 }
                 '$' => {
-                    push_text(&mut tokens, &mut current_text);
+                    push_text(&mut tokens, &mut spans, self.input, &mut current_text);
                     tokens.push(Token::InlineMath(self.advance_until('$')));
                 }
                 '`' => {
-                    push_text(&mut tokens, &mut current_text);
-                    if self.next_are(vec!['`'; 2]) {
+                    push_text(&mut tokens, &mut spans, self.input, &mut current_text);
+                    if self.next_are("``") {
                         self.advance();
                         self.advance();
                         tokens.push(Token::CodeBlock(Lexer::remove_indents(
-                            self.advance_until_chars(vec!['`'; 3]),
+                            Cow::Borrowed(self.advance_until_chars("```")),
                             0,
                         )));
                     } else {
                         tokens.push(Token::InlineCode(self.advance_until('`')));
                     }
                 }
-                '\\' => {
-                    if self.next_is('[') {
-                        self.advance();
-                        push_text(&mut tokens, &mut current_text);
-                        tokens.push(Token::DisplayMath(
-                            self.advance_until_chars(vec!['\\', ']']),
-                        ));
+                '\\' if self.next_are("ref{") || self.next_are("eqref{") => {
+                    push_text(&mut tokens, &mut spans, self.input, &mut current_text);
+                    let equation = self.next_is('e');
+                    self.advance_until('{');
+                    let label = self.advance_until('}');
+                    tokens.push(if equation {
+                        Token::EqRef(label)
                     } else {
-                        current_text.push(*current);
-                    }
+                        Token::Ref(label)
+                    });
+                }
+                '\\' if self.next_is('[') => {
+                    self.advance();
+                    push_text(&mut tokens, &mut spans, self.input, &mut current_text);
+                    tokens.push(Token::DisplayMath(self.advance_until_chars("\\]")));
                 }
                 '%' => {
+                    push_text(&mut tokens, &mut spans, self.input, &mut current_text);
                     self.advance_while('%');
-                    if self.next_is('\n') {
-                        tokens.push(Token::EnvEnd);
+                    let depth = u8::try_from(self.pos - start).unwrap_or(u8::MAX);
+                    if self.eof() || self.next_is('\n') {
+                        tokens.push(Token::EnvEnd(depth));
                     } else {
-                        let name = self.advance_until_one_of_excluded(vec![' ', '\n']);
+                        let name = self.advance_until_one_of_excluded(&[' ', '\n']);
                         if self.next_is(' ') {
                             self.advance();
                         }
-                        tokens.push(Token::EnvBegin(name));
+                        tokens.push(Token::EnvBegin(depth, name));
+                        // Paths and the like, taken as written
+                        if name == "include" {
+                            spans.resize(tokens.len(), Span::new(start, self.pos));
+                            current_text.start = self.pos;
+                            self.advance_until_one_of_excluded(&['\n']);
+                            current_text.end = self.pos;
+                        }
                     }
                 }
                 _ => {
-                    current_text.push(*current);
+                    if current_text.is_empty() {
+                        current_text.start = start;
+                    }
+                    current_text.end = self.pos;
                 }
             }
             line_begins = false;
         }
 
-        if !current_text.is_empty() {
-            tokens.push(Token::Text(current_text.iter().collect::<String>()));
-            current_text.clear();
-        }
+        push_text(&mut tokens, &mut spans, self.input, &mut current_text);
 
-        tokens
+        (tokens, spans)
     }
 
     fn advance(&mut self) -> Option<char> {
-        self.pos += 1;
-        self.input.chars().nth(self.pos - 1)
+        let next = self.next()?;
+        self.pos += next.len_utf8();
+        Some(next)
     }
     fn next(&self) -> Option<char> {
-        self.input.chars().nth(self.pos)
+        self.input[self.pos..].chars().next()
     }
     fn next_is(&self, what: char) -> bool {
-        if let Some(next_char) = self.input.chars().nth(self.pos) {
-            return next_char == what;
-        }
-        return false;
+        self.next() == Some(what)
     }
-    fn next_are(&self, what: Vec<char>) -> bool {
-        let mut offset = 0;
-        for _ in &what {
-            if self.pos + offset < self.input.len()
-                && &what[offset] == &self.input.chars().nth(self.pos + offset).unwrap()
-            {
-                offset += 1
-            } else {
-                return false;
-            }
-        }
-        return true;
+    fn next_are(&self, what: &str) -> bool {
+        self.input[self.pos..].starts_with(what)
     }
     fn eof(&self) -> bool {
         self.pos >= self.input.len()
@@ -248,41 +315,29 @@ impl<'a> Lexer<'a> {
             self.advance();
         }
     }
-    fn advance_until(&mut self, until: char) -> String {
-        let mut consumed: Vec<char> = vec![];
-        while !self.eof() && !self.next_is(until) {
-            consumed.push(self.advance().unwrap());
-        }
+    fn advance_until(&mut self, until: char) -> &'a str {
+        let consumed = self.advance_until_one_of_excluded(&[until]);
         self.advance();
-        consumed.iter().collect::<String>()
+        consumed
     }
-    fn advance_until_one_of_excluded(&mut self, until: Vec<char>) -> String {
-        let mut consumed: Vec<char> = vec![];
-        fn check_next(next: &Option<char>, until: &Vec<char>) -> bool {
-            if let Some(n) = next {
-                until.contains(n)
-            } else {
-                false
-            }
-        }
-        while !self.eof() && !check_next(&self.next(), &until) {
-            consumed.push(self.advance().unwrap());
-        }
-        consumed.iter().collect::<String>()
+    fn advance_until_one_of_excluded(&mut self, until: &[char]) -> &'a str {
+        let start = self.pos;
+        self.pos = self.input[start..]
+            .find(until)
+            .map_or(self.input.len(), |offset| start + offset);
+        &self.input[start..self.pos]
     }
-    fn advance_until_chars(&mut self, until: Vec<char>) -> String {
-        let mut consumed: Vec<char> = vec![];
-
-        fn check_previous(consumed: &Vec<char>, until: &Vec<char>) -> bool {
-            consumed.len() >= until.len() && *until == consumed[(consumed.len() - until.len())..]
-        }
-
-        while !self.eof() && !check_previous(&consumed, &until) {
-            consumed.push(self.advance().unwrap());
+    fn advance_until_chars(&mut self, until: &str) -> &'a str {
+        let start = self.pos;
+        match self.input[start..].find(until) {
+            Some(offset) => {
+                self.pos = start + offset + until.len();
+                &self.input[start..start + offset]
+            }
+            None => {
+                self.pos = self.input.len();
+                &self.input[start..]
+            }
         }
-
-        consumed[..consumed.len() - until.len()]
-            .iter()
-            .collect::<String>()
     }
 }
@@ -0,0 +1,193 @@
+//! Parser and renderers for a Markdown dialect geared towards math notes
+//!
+//! ```
+//! let document = md_parser::parse("# Title\n\nSome **bold** text\n");
+//! let html = md_parser::to_html(&document, &md_parser::Options::default());
+//! assert!(html.contains("<strong>bold</strong>"));
+//! ```
+
+pub mod commonmark;
+pub mod diagnostics;
+pub mod document;
+pub mod environments;
+pub mod fmt;
+pub mod headings;
+pub mod html;
+pub mod import;
+pub mod include;
+pub mod latex;
+pub mod lexer;
+pub mod numbering;
+pub mod pandoc;
+pub mod parser;
+pub mod references;
+pub mod render;
+pub mod toc;
+pub mod typst;
+
+pub use commonmark::CommonMarkRenderer;
+pub use diagnostics::{Diagnostic, Severity};
+pub use document::{Document, Metadata};
+pub use environments::{EnvStyle, EnvType, EnvTypes, Param, ParamKind, ParamValue, Params};
+pub use html::{nodes_to_html, ConcealMode, HtmlRenderer, Options, SolutionMode};
+pub use include::SourceFile;
+pub use latex::LatexRenderer;
+pub use lexer::{HrStyle, Lexer, Span, Token};
+pub use numbering::Numbering;
+pub use pandoc::PandocRenderer;
+pub use parser::{Label, ListType, Node, OwnedNode, Parser};
+pub use render::Renderer;
+pub use toc::{TocEntry, TocKind};
+pub use typst::TypstRenderer;
+
+use std::path::PathBuf;
+
+/// Settings for `parse_with`
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    pub environments: EnvTypes,
+    pub numbering: Numbering,
+    /// File the input was read from, which `%include` paths are relative to; the current
+    /// directory when `None`
+    pub path: Option<PathBuf>,
+    /// Treat the input as untrusted: refuse `%include` and enforce these limits
+    ///
+    /// This only covers parsing: render the document with `Options::safe` as well, which drops
+    /// raw HTML and unsafe links.
+    pub safe: Option<Limits>,
+}
+
+/// Bounds on untrusted input, so that it cannot exhaust the stack or memory
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Deepest nesting of environments, lists and inline formatting; deeper content is left out
+    pub max_depth: usize,
+    /// Longest input in bytes; longer ones are not parsed at all
+    pub max_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_depth: 32,
+            max_size: 1 << 20,
+        }
+    }
+}
+
+/// Lexes and parses a whole document, front matter included
+pub fn parse(input: &str) -> Document<'_> {
+    parse_with(input, &ParseOptions::default())
+}
+
+pub fn parse_with<'a>(input: &'a str, options: &ParseOptions) -> Document<'a> {
+    if let Some(document) = oversized(input, options) {
+        return document;
+    }
+    let (metadata, body) = Metadata::parse(input);
+    let (mut nodes, mut diagnostics) = parse_nodes(body, input.len() - body.len(), 0, options);
+    let mut files = vec![];
+    include::expand(&mut nodes, options, &mut files, &mut diagnostics);
+    headings::assign(&mut nodes);
+    numbering::assign(&mut nodes, &options.numbering);
+    references::resolve(&mut nodes, &mut diagnostics);
+    toc::fill(&mut nodes);
+    Document {
+        metadata,
+        nodes,
+        diagnostics,
+        files,
+    }
+}
+
+/// Empty document reporting an input over the size limit of safe mode
+fn oversized<'a>(input: &str, options: &ParseOptions) -> Option<Document<'a>> {
+    let limits = options
+        .safe
+        .filter(|limits| input.len() > limits.max_size)?;
+    Some(Document {
+        metadata: Metadata::default(),
+        nodes: vec![],
+        diagnostics: vec![Diagnostic::error(
+            format!(
+                "input is {} bytes long, over the limit of {}",
+                input.len(),
+                limits.max_size
+            ),
+            Span::default(),
+        )],
+        files: vec![],
+    })
+}
+
+/// Lexes and parses the part of a file following its front matter, which starts `offset` bytes
+/// into source `file`
+fn parse_nodes<'a>(
+    body: &'a str,
+    offset: usize,
+    file: usize,
+    options: &ParseOptions,
+) -> (Vec<Node<'a>>, Vec<Diagnostic>) {
+    let (tokens, mut spans) = Lexer::new(body).tokenize_with_spans();
+    for span in &mut spans {
+        *span = span.shifted(offset).in_file(file);
+    }
+    let mut parser = Parser::document(&tokens, &spans).with_environments(&options.environments);
+    if let Some(limits) = options.safe {
+        parser = parser.with_max_depth(limits.max_depth);
+    }
+    let nodes = parser.parse(false);
+    (nodes, parser.into_diagnostics())
+}
+
+/// Converts a CommonMark file into a document, with warnings where the dialects differ
+///
+/// The front matter, if any, is read as in `parse_with`; the body is read by `import::commonmark`
+/// and then goes through the same passes as a parsed one.
+pub fn from_commonmark<'a>(input: &'a str, options: &ParseOptions) -> Document<'a> {
+    if let Some(document) = oversized(input, options) {
+        return document;
+    }
+    let (metadata, body) = Metadata::parse(input);
+    let (mut nodes, mut diagnostics) = import::commonmark(body, input.len() - body.len(), options);
+    headings::assign(&mut nodes);
+    numbering::assign(&mut nodes, &options.numbering);
+    references::resolve(&mut nodes, &mut diagnostics);
+    toc::fill(&mut nodes);
+    Document {
+        metadata,
+        nodes,
+        diagnostics,
+        files: vec![],
+    }
+}
+
+/// Renders a document to HTML
+pub fn to_html(document: &Document, options: &Options) -> String {
+    html::render(document, options)
+}
+
+/// Renders a document to LaTeX
+pub fn to_latex(document: &Document, options: &Options) -> String {
+    latex::render(document, options)
+}
+
+/// Renders a document to Typst, along with warnings for the formulas left untranslated
+pub fn to_typst(document: &Document, options: &Options) -> (String, Vec<Diagnostic>) {
+    typst::render(document, options)
+}
+
+/// Renders a document to CommonMark, along with warnings for what it cannot express
+pub fn to_commonmark(document: &Document, options: &Options) -> (String, Vec<Diagnostic>) {
+    commonmark::render(document, options)
+}
+
+/// Renders a document to Pandoc's JSON AST, for `pandoc -f json`
+pub fn to_pandoc(document: &Document, options: &Options) -> String {
+    pandoc::render(document, options)
+}
+
+/// Rewrites a file's source in a canonical layout, see `fmt::format`
+pub fn format(input: &str, options: &ParseOptions) -> String {
+    fmt::format(input, options)
+}
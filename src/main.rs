@@ -1,109 +1,134 @@
-mod lexer;
-mod parser;
-use lexer::{HrStyle, Lexer, Token};
-use parser::{EnvType, Node, Parser};
-use std::fs;
+use md_parser::{diagnostics, ConcealMode, EnvTypes, Limits, Options, ParseOptions, SolutionMode};
+use std::{env, fs, process};
 
-fn nodes_to_html(nodes: &Vec<Node>) -> String {
-    let mut node_str: Vec<String> = vec![];
-    for node in nodes {
-        node_str.push(match node {
-            Node::NewLine => "<br/>".to_string(),
-            Node::Header { level, children } => {
-                format!("<h{level}>{}</h{level}>", nodes_to_html(children))
-            }
-            Node::Text(text) => text.to_string(),
-            Node::Paragraph(children) => format!("<p>{}</p>", nodes_to_html(children)),
-            Node::Bold(children) => format!("<strong>{}</strong>", nodes_to_html(children)),
-            Node::Italic(children) => format!("<em>{}</em>", nodes_to_html(children)),
-            Node::Striked(children) => format!("<s>{}</s>", nodes_to_html(children)),
-            Node::Underline(children) => format!("<u>{}</u>", nodes_to_html(children)),
-            Node::Highlighted(children) => format!("<mark>{}</mark>", nodes_to_html(children)),
-            Node::InlineMath(math) => format!("<span class=\"math-inline\">{}</span>", math),
-            Node::DisplayMath(math) => format!("<span class=\"math-display\">{}</span>", math),
-            Node::InlineCode(code) => format!("<code class=\"inline\">{}</code>", code),
-            Node::CodeBlock { language, code } => {
-                format!(
-                    "<pre><code class=\"block\"{}>{code}</code></pre>",
-                    if let Some(lang) = language {
-                        format!(" lang=\"{}\"", lang)
-                    } else {
-                        String::new()
+fn main() {
+    let mut options = Options {
+        standalone: true,
+        ..Options::default()
+    };
+    let mut parse_options = ParseOptions::default();
+    let mut format = String::from("html");
+    let mut commonmark_input = false;
+    let mut paths: Vec<String> = vec![];
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--number-sections" => options.number_sections = true,
+            "--toc" => options.toc_sidebar = true,
+            "--toc-environments" => options.toc_environments = true,
+            "--number-within-sections" => parse_options.numbering.reset_depth = Some(1),
+            "--to" => {
+                format = match args.next() {
+                    Some(format)
+                        if matches!(
+                            format.as_str(),
+                            "html" | "latex" | "typst" | "commonmark" | "pandoc" | "md"
+                        ) =>
+                    {
+                        format
                     }
-                )
+                    _ => fail("--to expects html, latex, typst, commonmark, pandoc or md"),
+                }
             }
-            Node::Env {
-                environment_type,
-                environment_arg,
-                children,
-            } => {
-                let env_type = match environment_type {
-                    EnvType::Definition => "definition",
-                    EnvType::Theorem => "theorem",
-                    EnvType::Corollary => "corollary",
-                    EnvType::Lemma => "lemma",
-                    EnvType::Remark => "remark",
-                    EnvType::Example => "example",
-                    EnvType::Exercise => "exercise",
-                    EnvType::Fold => "fold",
-                    EnvType::Conceal => "conceal",
-                };
-
-                let env_name = if let Some(name) = environment_arg {
-                    format!("<div class=\"environment-name\">{}</div>", {
-                        nodes_to_html(name)
-                    })
-                } else {
-                    String::new()
-                };
-                format!(
-                    "<div class=\"environment environment-{env_type}\">{env_name}{}</div>",
-                    nodes_to_html(children)
-                )
+            "--from" => match args.next().as_deref() {
+                Some("md") => commonmark_input = false,
+                Some("commonmark") => commonmark_input = true,
+                _ => fail("--from expects md or commonmark"),
+            },
+            "--safe" => {
+                options.safe = true;
+                parse_options.safe = Some(Limits::default());
             }
-            Node::List {
-                list_type: _,
-                children,
-            } => format!("<ul>{}</ul>", nodes_to_html(children)),
-            Node::ListItem(children) => format!("<li>{}</li>", nodes_to_html(children)),
-            Node::Hr(style) => {
-                format!(
-                    "<hr class=\"style-{}\"/>",
-                    match style {
-                        HrStyle::Normal => "normal",
-                        HrStyle::Dashed => "dashed",
-                        HrStyle::Dotted => "dotted",
-                        HrStyle::Sawtooth => "sawtooth",
+            "--conceal" => {
+                options.conceal = match args.next().as_deref() {
+                    Some("hide") => ConcealMode::Hide,
+                    Some("reveal") => ConcealMode::Reveal,
+                    Some("strip") => ConcealMode::Strip,
+                    _ => fail("--conceal expects hide, reveal or strip"),
+                }
+            }
+            "--conceal-level" => {
+                options.conceal_level = match args.next().map(|level| level.parse()) {
+                    Some(Ok(level)) => Some(level),
+                    _ => fail("--conceal-level expects an integer"),
+                }
+            }
+            "--conceal-categ" => {
+                let categories = args
+                    .next()
+                    .unwrap_or_else(|| fail("--conceal-categ expects categories"));
+                options.conceal_categories = categories.split(',').map(str::to_string).collect();
+            }
+            "--solutions" => {
+                options.solutions = match args.next().as_deref() {
+                    Some("inline") => SolutionMode::Inline,
+                    Some("collect") => SolutionMode::Collect,
+                    Some("strip") => SolutionMode::Strip,
+                    _ => fail("--solutions expects inline, collect or strip"),
+                }
+            }
+            "--environments" => {
+                let path = args
+                    .next()
+                    .unwrap_or_else(|| fail("--environments expects a file"));
+                let config = fs::read_to_string(&path).expect("Error reading environments file");
+                parse_options.environments = match EnvTypes::default().load(&config) {
+                    Ok(environments) => environments,
+                    Err(error) => {
+                        eprintln!("{path}:{}: error: {}", error.line, error.message);
+                        process::exit(1);
                     }
-                )
+                };
             }
-            Node::NBSP => "&nbsp;".to_string(),
-            _ => todo!(),
-        });
+            _ => paths.push(arg),
+        }
     }
-    node_str.join("")
-}
-
-fn main() {
-    let input = fs::read_to_string("test.md").expect("Error reading file");
-
-    let mut lexer = Lexer::new(input.as_str());
-    let tokens = lexer.tokenize();
-
-    dbg!(&tokens);
+    let mut paths = paths.into_iter();
+    let input_path = paths.next().unwrap_or_else(|| "test.md".to_string());
+    let output_path = paths.next().unwrap_or_else(|| "out.html".to_string());
 
-    let mut parser = Parser::new(Parser::preprocess(tokens));
-    let nodes = parser.parse(false);
+    let input = fs::read_to_string(&input_path).expect("Error reading file");
+    parse_options.path = Some(input_path.clone().into());
+    let document = match commonmark_input {
+        true => md_parser::from_commonmark(&input, &parse_options),
+        false => md_parser::parse_with(&input, &parse_options),
+    };
 
-    dbg!(&nodes);
+    let (output, render_diagnostics) = match format.as_str() {
+        "latex" => (md_parser::to_latex(&document, &options), vec![]),
+        "typst" => md_parser::to_typst(&document, &options),
+        "commonmark" => md_parser::to_commonmark(&document, &options),
+        "pandoc" => (md_parser::to_pandoc(&document, &options), vec![]),
+        "md" if commonmark_input => {
+            let (_, body) = md_parser::Metadata::parse(&input);
+            let front_matter = &input[..input.len() - body.len()];
+            let body = md_parser::fmt::nodes_to_source(&document.nodes);
+            (format!("{front_matter}{body}"), vec![])
+        }
+        "md" => (md_parser::format(&input, &parse_options), vec![]),
+        _ => (md_parser::to_html(&document, &options), vec![]),
+    };
 
-    let out = nodes_to_html(&nodes);
-
-    // println!("IN: {:?}", input);
-    // println!("OUT: {:?}", out);
+    for diagnostic in document.diagnostics.iter().chain(&render_diagnostics) {
+        let (path, source) = match diagnostic.span.file {
+            0 => (input_path.clone(), input.as_str()),
+            file => {
+                let file = &document.files[file - 1];
+                (file.path.display().to_string(), file.source.as_str())
+            }
+        };
+        let (line, column) = diagnostics::line_col(source, diagnostic.span.start);
+        eprintln!(
+            "{path}:{line}:{column}: {}: {}",
+            diagnostic.severity, diagnostic.message
+        );
+    }
 
-    let head = "<style>p {padding: 1rem; border: 1px dashed red;} .math-inline{font-family: monospace; font-weight: bold; color: grey;} .math-display{font-family: monospace; font-weight: bold; color: grey; display: block; padding: 1rem; text-align: center; font-size: 2rem;} hr {margin-top: 2px solid gray;} hr.style-dashed {border-style: dashed;} hr.style-dotted {border-style: dotted;} hr.style-sawtooth {border-image: url('data:image/svg+xml,%3Csvg xmlns%3D%22http%3A//www.w3.org/2000/svg%22 viewBox%3D%220 0 12 8%22 width%3D%2212%22 height%3D%228%22%3E%3Cpath fill%3D%22none%22 stroke%3D%22rgba(191%2C191%2C191%2C0.9)%22 stroke-width%3D%221.5%22 d%3D%22M0%2C0 6%2C8 12%2C0%22/%3E%3C/svg%3E') 0 0 100% repeat; border-width: 0 0 10px; border-style: solid; position: relative;} .environment {background-color: lightgray; padding: 1rem; border: 1px solid black;} .environment-name {border-bottom: 1px solid black; margin-bottom: 1rem;}</style>";
+    fs::write(output_path, output).expect("Could not write to file");
+}
 
-    fs::write("out.html", format!("<head>{head}</head><body>{out}</body>"))
-        .expect("Could not write to file");
+/// Reports a bad command line argument and exits
+fn fail(message: &str) -> ! {
+    eprintln!("error: {message}");
+    process::exit(1);
 }
@@ -0,0 +1,101 @@
+use crate::headings;
+use crate::parser::Node;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Default)]
+pub struct Numbering {
+    /// Restart counters at every section of this depth or above, and prefix numbers with the
+    /// section number
+    pub reset_depth: Option<usize>,
+}
+
+/// Numbers environments and labelled equations in document order, and gives environments an
+/// anchor
+///
+/// Anchors are the type's name and the number, with a `-1`, `-2`… suffix when a header or a label
+/// already has that id. Headers must have gone through `headings::assign` first.
+pub fn assign(nodes: &mut [Node], numbering: &Numbering) {
+    let mut counters: HashMap<String, u32> = HashMap::new();
+    let mut section: Vec<u32> = vec![];
+    let mut used: HashSet<String> = HashSet::new();
+    headings::walk(nodes, &mut |node| match node {
+        Node::Header { id: Some(id), .. } => {
+            used.insert(id.to_string());
+        }
+        Node::Env {
+            label: Some(label), ..
+        }
+        | Node::DisplayMath {
+            label: Some(label), ..
+        } => {
+            used.insert(label.name.to_string());
+        }
+        _ => (),
+    });
+
+    headings::walk(nodes, &mut |node| match node {
+        Node::Header { number, .. } => {
+            if numbering
+                .reset_depth
+                .is_some_and(|depth| number.len() <= depth)
+            {
+                counters.clear();
+            }
+            section.clone_from(number);
+        }
+        Node::Env {
+            environment_type,
+            label,
+            id,
+            number,
+            ..
+        } => {
+            if let Some(counter) = &environment_type.counter {
+                *number = next(&mut counters, counter, &section, numbering.reset_depth);
+            }
+
+            if let Some(label) = label {
+                *id = Some(label.name.clone());
+            } else if id.is_none() && !number.is_empty() {
+                let suffix = headings::format_number(number).replace('.', "-");
+                let base = format!("{}-{suffix}", environment_type.name);
+                let mut candidate = base.clone();
+                let mut suffix = 0;
+                while used.contains(&candidate) {
+                    suffix += 1;
+                    candidate = format!("{base}-{suffix}");
+                }
+                used.insert(candidate.clone());
+                *id = Some(Cow::Owned(candidate));
+            }
+        }
+        Node::DisplayMath {
+            label: Some(_),
+            number,
+            ..
+        } => *number = next(&mut counters, "equation", &section, numbering.reset_depth),
+        _ => (),
+    });
+}
+
+fn next(
+    counters: &mut HashMap<String, u32>,
+    counter: &str,
+    section: &[u32],
+    reset_depth: Option<usize>,
+) -> Vec<u32> {
+    let count = counters.entry(counter.to_string()).or_insert(0);
+    *count += 1;
+
+    let mut number = match reset_depth {
+        Some(depth) => {
+            let mut prefix = section.to_vec();
+            prefix.resize(depth, 0);
+            prefix
+        }
+        None => vec![],
+    };
+    number.push(*count);
+    number
+}
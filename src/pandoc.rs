@@ -0,0 +1,672 @@
+use crate::document::Document;
+use crate::environments::ParamValue;
+use crate::headings;
+use crate::html::{self, ConcealMode, Options, SolutionMode};
+use crate::lexer::{HrStyle, Span};
+use crate::parser::{Label, ListType, Node};
+use crate::references::Target;
+use crate::render::{self, environment_id, is_concealed, Renderer};
+use crate::toc::{TocEntry, TocKind};
+
+/// Version of Pandoc's document model the output follows
+const API_VERSION: &str = "[1,23,1]";
+
+/// Backend writing Pandoc's JSON AST, which `pandoc -f json` converts to any of its formats
+///
+/// Environments become `Div`s classed `environment` and their type's name, with their parameters
+/// as attributes and their title in a leading `Div` classed `environment-name`; underline and
+/// highlight become `Span`s classed `underline` and `mark`, and rules of another style than the
+/// plain one are wrapped in a `Div` naming it. Every hook returns JSON values each followed by a
+/// comma, so that rendered nodes can be concatenated like in the other backends.
+#[derive(Debug, Clone, Default)]
+pub struct PandocRenderer {
+    options: Options,
+}
+
+impl PandocRenderer {
+    pub fn new(options: Options) -> Self {
+        Self { options }
+    }
+
+    fn lang(&self) -> &str {
+        self.options.lang.as_deref().unwrap_or("en")
+    }
+
+    /// Renders `nodes` as blocks, the runs of inline nodes between them going into a `wrapper`
+    /// block (`Para` or `Plain`), with `qed` ending the last one
+    fn blocks(&mut self, nodes: &[Node], wrapper: &str, qed: Option<&str>) -> String {
+        let last_block = nodes.iter().rposition(is_block);
+        let trailing_run = nodes[last_block.map_or(0, |index| index + 1)..]
+            .iter()
+            .any(|node| !matches!(node, Node::NewLine));
+        let mut out = String::new();
+        let mut run = None;
+        for (index, node) in nodes.iter().enumerate() {
+            if !is_block(node) {
+                run.get_or_insert(index);
+                continue;
+            }
+            if let Some(start) = run.take() {
+                out.push_str(&self.inlines(&nodes[start..index], wrapper, None));
+            }
+            let mark = qed.filter(|_| !trailing_run && Some(index) == last_block);
+            match (node, mark) {
+                (Node::Paragraph(children), Some(_)) => {
+                    out.push_str(&self.blocks(children, "Para", mark));
+                }
+                (_, Some(mark)) => {
+                    out.push_str(&self.node(node));
+                    out.push_str(&element("Plain", &array(&str_element(mark))));
+                }
+                (_, None) => out.push_str(&self.node(node)),
+            }
+        }
+        match run {
+            Some(start) => out.push_str(&self.inlines(&nodes[start..], wrapper, qed)),
+            None if last_block.is_none() => {
+                if let Some(mark) = qed {
+                    out.push_str(&element("Plain", &array(&str_element(mark))));
+                }
+            }
+            None => (),
+        }
+        out
+    }
+
+    /// Block `wrapper` holding inline `nodes`, the line breaks around them left out
+    fn inlines(&mut self, nodes: &[Node], wrapper: &str, qed: Option<&str>) -> String {
+        let first = nodes
+            .iter()
+            .position(|node| !matches!(node, Node::NewLine))
+            .unwrap_or(nodes.len());
+        let last = nodes
+            .iter()
+            .rposition(|node| !matches!(node, Node::NewLine))
+            .map_or(first, |last| last + 1);
+        let mut content = self.nodes(&nodes[first..last]);
+        if let Some(mark) = qed {
+            if !content.is_empty() {
+                content.push_str(&empty_element("Space"));
+            }
+            content.push_str(&str_element(mark));
+        }
+        if content.is_empty() {
+            return content;
+        }
+        element(wrapper, &array(&content))
+    }
+
+    /// Renders an environment, citing the statement it is attached to when `link_statement` is
+    /// set or when it is given with `of`
+    fn environment_block(&mut self, node: &Node, link_statement: bool) -> String {
+        let Node::Env {
+            environment_type,
+            environment_arg,
+            params,
+            attached,
+            number,
+            children,
+            ..
+        } = node
+        else {
+            return self.node(node);
+        };
+        let concealed = environment_type.conceal && is_concealed(params, &self.options);
+        if concealed && self.options.conceal == ConcealMode::Strip {
+            return String::new();
+        }
+
+        let mut classes = vec!["environment", environment_type.name.as_str()];
+        if environment_type.is_folded(params) {
+            classes.push("folded");
+        }
+        if concealed && self.options.conceal == ConcealMode::Hide {
+            classes.push("concealed");
+        }
+        let mut attributes = vec![(
+            "style".to_string(),
+            environment_type.style.name().to_string(),
+        )];
+        if !number.is_empty() {
+            attributes.push(("number".to_string(), headings::format_number(number)));
+        }
+        for (name, value) in params.iter() {
+            let value = match value {
+                ParamValue::Flag(flag) => flag.to_string(),
+                ParamValue::Integer(integer) => integer.to_string(),
+                ParamValue::Text(text) => text.to_string(),
+            };
+            attributes.push((name.to_string(), value));
+        }
+        let id = environment_id(node).unwrap_or_default();
+
+        let mut details = vec![];
+        if let Some(statement) = attached
+            .as_ref()
+            .filter(|_| link_statement || params.text("of").is_some())
+        {
+            let name = statement_name(statement, self.lang());
+            details.push(link_element(&words(&name), &format!("#{}", statement.id)));
+        }
+        if let Some(arg) = environment_arg {
+            details.push(self.nodes(arg));
+        }
+        details.retain(|detail| !detail.is_empty());
+        let mut title = String::new();
+        if let Some(label) = environment_type.label_for(self.lang()) {
+            let label = match number.is_empty() {
+                true => label.to_string(),
+                false => format!("{label} {}", headings::format_number(number)),
+            };
+            title.push_str(&element("Strong", &array(&words(&label))));
+        }
+        if !details.is_empty() {
+            let details = details.join(&format!("{}{}", str_element(","), empty_element("Space")));
+            if title.is_empty() {
+                title = details;
+            } else {
+                title.push_str(&format!("{}{}", empty_element("Space"), str_element("(")));
+                title.push_str(&details);
+                title.push_str(&str_element(")"));
+            }
+        }
+
+        let mut content = String::new();
+        if !title.is_empty() {
+            let plain = element("Plain", &array(&title));
+            content.push_str(&element(
+                "Div",
+                &format!(
+                    "[{},{}]",
+                    attr("", &["environment-name"], &[]),
+                    array(&plain)
+                ),
+            ));
+        }
+        content.push_str(&match &environment_type.qed {
+            Some(mark) => self.nodes_with_qed(children, mark),
+            None => self.blocks(children, "Plain", None),
+        });
+        element(
+            "Div",
+            &format!("[{},{}]", attr(&id, &classes, &attributes), array(&content)),
+        )
+    }
+
+    fn toc_list(&mut self, entries: &[TocEntry]) -> String {
+        let mut items = String::new();
+        for entry in entries {
+            let title = match &entry.kind {
+                TocKind::Section
+                    if self
+                        .options
+                        .toc_depth
+                        .is_some_and(|depth| entry.depth() > depth) =>
+                {
+                    continue
+                }
+                TocKind::Section if self.options.number_sections => format!(
+                    "{}{}{}",
+                    str_element(&headings::format_number(&entry.number)),
+                    empty_element("Space"),
+                    self.nodes(&entry.title)
+                ),
+                TocKind::Section => self.nodes(&entry.title),
+                TocKind::Environment(_) if !self.options.toc_environments => continue,
+                TocKind::Environment(environment_type) => {
+                    let label = environment_type
+                        .label_for(self.lang())
+                        .unwrap_or(&environment_type.name);
+                    let mut title = words(&format!(
+                        "{label} {}",
+                        headings::format_number(&entry.number)
+                    ));
+                    if !entry.title.is_empty() {
+                        title.push_str(&format!(
+                            "{}{}{}{}",
+                            empty_element("Space"),
+                            str_element("("),
+                            self.nodes(&entry.title),
+                            str_element(")")
+                        ));
+                    }
+                    title
+                }
+            };
+            let link = link_element(&title, &format!("#{}", entry.id));
+            let mut item = element("Plain", &array(&link));
+            item.push_str(&self.toc_list(&entry.children));
+            items.push_str(&array(&item));
+            items.push(',');
+        }
+        if items.is_empty() {
+            return items;
+        }
+        element("BulletList", &array(&items))
+    }
+}
+
+impl Renderer for PandocRenderer {
+    fn options(&self) -> &Options {
+        &self.options
+    }
+
+    fn document(&mut self, document: &Document) -> String {
+        let blocks = match self.options.solutions {
+            SolutionMode::Collect => render::chapters(self, &document.nodes),
+            _ => self.blocks(&document.nodes, "Para", None),
+        };
+        let entries = &document.metadata.entries;
+        let mut meta = vec![];
+        for (index, (key, value)) in entries.iter().enumerate() {
+            // A key given twice keeps its last value, as in `Metadata::get`
+            if entries[index + 1..].iter().any(|(other, _)| other == key) {
+                continue;
+            }
+            meta.push(format!(
+                "{}:{{\"t\":\"MetaString\",\"c\":{}}}",
+                string(key),
+                string(value)
+            ));
+        }
+        format!(
+            "{{\"pandoc-api-version\":{API_VERSION},\"meta\":{{{}}},\"blocks\":{}}}\n",
+            meta.join(","),
+            array(&blocks)
+        )
+    }
+
+    /// Blocks when there is any among `nodes`, which only happens where blocks are expected,
+    /// and inline content otherwise
+    fn nodes(&mut self, nodes: &[Node]) -> String {
+        if nodes.iter().any(is_block) {
+            return self.blocks(nodes, "Para", None);
+        }
+        nodes.iter().map(|node| self.node(node)).collect()
+    }
+
+    /// Blocks, however little of them the chapter has
+    fn chapter(&mut self, nodes: &[Node]) -> String {
+        self.blocks(nodes, "Para", None)
+    }
+
+    fn header(&mut self, level: u8, id: Option<&str>, number: &[u32], children: &[Node]) -> String {
+        let mut attributes = vec![];
+        if self.options.number_sections && !number.is_empty() {
+            attributes.push(("number".to_string(), headings::format_number(number)));
+        }
+        element(
+            "Header",
+            &format!(
+                "[{level},{},{}]",
+                attr(id.unwrap_or_default(), &[], &attributes),
+                array(&self.nodes(children))
+            ),
+        )
+    }
+
+    fn bold(&mut self, children: &[Node]) -> String {
+        element("Strong", &array(&self.nodes(children)))
+    }
+
+    fn italic(&mut self, children: &[Node]) -> String {
+        element("Emph", &array(&self.nodes(children)))
+    }
+
+    fn striked(&mut self, children: &[Node]) -> String {
+        element("Strikeout", &array(&self.nodes(children)))
+    }
+
+    fn underline(&mut self, children: &[Node], _span: Span) -> String {
+        span_element("underline", &self.nodes(children))
+    }
+
+    fn highlighted(&mut self, children: &[Node], _span: Span) -> String {
+        span_element("mark", &self.nodes(children))
+    }
+
+    fn link(&mut self, url: &str, children: &[Node]) -> String {
+        if self.options.safe && !html::is_safe_url(url) {
+            return self.nodes(children);
+        }
+        link_element(&self.nodes(children), url)
+    }
+
+    fn list(&mut self, list_type: &ListType, children: &[Node]) -> String {
+        let kind = match list_type {
+            ListType::Normal => "BulletList",
+        };
+        element(kind, &array(&self.nodes(children)))
+    }
+
+    fn list_item(&mut self, children: &[Node]) -> String {
+        format!("{},", array(&self.blocks(children, "Plain", None)))
+    }
+
+    fn inline_code(&mut self, code: &str) -> String {
+        element(
+            "Code",
+            &format!("[{},{}]", attr("", &[], &[]), string(code)),
+        )
+    }
+
+    fn code_block(&mut self, language: Option<&str>, code: &str) -> String {
+        // Parsed blocks keep the rest of their opening line as the first line of their code
+        let (language, code) = match language {
+            Some(language) => (language, code),
+            None => code.split_once('\n').unwrap_or(("", code)),
+        };
+        let language = language.trim();
+        let classes: &[&str] = if language.is_empty() {
+            &[]
+        } else {
+            &[language]
+        };
+        element(
+            "CodeBlock",
+            &format!(
+                "[{},{}]",
+                attr("", classes, &[]),
+                string(code.strip_suffix('\n').unwrap_or(code))
+            ),
+        )
+    }
+
+    fn raw_html(&mut self, html: &str) -> String {
+        if self.options.safe {
+            return String::new();
+        }
+        element("RawBlock", &format!("[\"html\",{}]", string(html)))
+    }
+
+    fn inline_math(&mut self, math: &str, _span: Span) -> String {
+        element(
+            "Math",
+            &format!("[{},{}]", empty_json("InlineMath"), string(math.trim())),
+        )
+    }
+
+    fn display_math(
+        &mut self,
+        math: &str,
+        _span: Span,
+        label: Option<&Label>,
+        number: &[u32],
+    ) -> String {
+        let tag = match number {
+            [] => String::new(),
+            number => format!(" \\tag{{{}}}", headings::format_number(number)),
+        };
+        let math = element(
+            "Math",
+            &format!(
+                "[{},{}]",
+                empty_json("DisplayMath"),
+                string(&format!("{}{tag}", math.trim()))
+            ),
+        );
+        match label {
+            Some(label) => element(
+                "Span",
+                &format!("[{},{}]", attr(&label.name, &[], &[]), array(&math)),
+            ),
+            None => math,
+        }
+    }
+
+    fn environment(&mut self, node: &Node) -> String {
+        match node {
+            Node::Env {
+                environment_type, ..
+            } if environment_type.solution && self.options.solutions != SolutionMode::Inline => {
+                match (self.options.solutions, environment_id(node)) {
+                    (SolutionMode::Collect, Some(id)) => {
+                        let label = environment_type
+                            .label_for(self.lang())
+                            .unwrap_or(&environment_type.name);
+                        let link = link_element(&words(label), &format!("#{id}"));
+                        element(
+                            "Div",
+                            &format!(
+                                "[{},{}]",
+                                attr("", &["solution-link"], &[]),
+                                array(&element("Plain", &array(&link)))
+                            ),
+                        )
+                    }
+                    _ => String::new(),
+                }
+            }
+            _ => self.environment_block(node, false),
+        }
+    }
+
+    /// `mark` ends the last paragraph, or follows the last block in one of its own
+    fn nodes_with_qed(&mut self, nodes: &[Node], mark: &str) -> String {
+        self.blocks(nodes, "Plain", Some(mark))
+    }
+
+    fn solutions(&mut self, solutions: &[&Node], level: u8) -> String {
+        let mut out = element(
+            "Header",
+            &format!(
+                "[{level},{},{}]",
+                attr("", &["unnumbered"], &[]),
+                array(&str_element("Solutions"))
+            ),
+        );
+        for solution in solutions {
+            out.push_str(&self.environment_block(solution, true));
+        }
+        out
+    }
+
+    fn new_line(&mut self) -> String {
+        empty_element("LineBreak")
+    }
+
+    fn paragraph(&mut self, children: &[Node]) -> String {
+        self.blocks(children, "Para", None)
+    }
+
+    fn text(&mut self, text: &str) -> String {
+        words(text)
+    }
+
+    fn hr(&mut self, style: &HrStyle, _span: Span) -> String {
+        let rule = empty_element("HorizontalRule");
+        let class = match style {
+            HrStyle::Normal => return rule,
+            HrStyle::Dashed => "rule-dashed",
+            HrStyle::Dotted => "rule-dotted",
+            HrStyle::Sawtooth => "rule-sawtooth",
+        };
+        element(
+            "Div",
+            &format!("[{},{}]", attr("", &[class], &[]), array(&rule)),
+        )
+    }
+
+    fn nbsp(&mut self) -> String {
+        str_element("\u{a0}")
+    }
+
+    fn table_of_contents(&mut self, entries: &[TocEntry]) -> String {
+        let list = self.toc_list(entries);
+        if list.is_empty() {
+            return list;
+        }
+        element(
+            "Div",
+            &format!("[{},{}]", attr("", &["toc"], &[]), array(&list)),
+        )
+    }
+
+    fn reference(&mut self, _label: &str, equation: bool, target: Option<&Target>) -> String {
+        match target {
+            Some(target) => {
+                let number = headings::format_number(&target.number);
+                let number = if equation {
+                    format!("({number})")
+                } else {
+                    number
+                };
+                link_element(&str_element(&number), &format!("#{}", target.id))
+            }
+            None => element("Strong", &array(&str_element("??"))),
+        }
+    }
+}
+
+/// Renders a document to Pandoc's JSON AST
+pub fn render(document: &Document, options: &Options) -> String {
+    let options = Options {
+        lang: document
+            .metadata
+            .lang()
+            .map(str::to_string)
+            .or_else(|| options.lang.clone()),
+        ..options.clone()
+    };
+    PandocRenderer::new(options).document(document)
+}
+
+/// "Théorème 2.3", or just the number for sections
+fn statement_name(target: &Target, lang: &str) -> String {
+    let number = headings::format_number(&target.number);
+    match target
+        .environment_type
+        .as_ref()
+        .and_then(|environment_type| environment_type.label_for(lang))
+    {
+        Some(label) if number.is_empty() => label.to_string(),
+        Some(label) => format!("{label} {number}"),
+        None => number,
+    }
+}
+
+/// Nodes that are blocks in Pandoc's model, display math being inline there
+fn is_block(node: &Node) -> bool {
+    matches!(
+        node,
+        Node::Header { .. }
+            | Node::List { .. }
+            | Node::ListItem(_)
+            | Node::CodeBlock { .. }
+            | Node::RawHtml(_)
+            | Node::Env { .. }
+            | Node::Paragraph(_)
+            | Node::Hr { .. }
+            | Node::TableOfContents(_)
+            | Node::Include { .. }
+    )
+}
+
+/// `{"t": kind, "c": content}`, followed by a comma like every rendered value
+fn element(kind: &str, content: &str) -> String {
+    format!("{{\"t\":\"{kind}\",\"c\":{content}}},")
+}
+
+/// Element without content, such as `Space`
+fn empty_element(kind: &str) -> String {
+    format!("{},", empty_json(kind))
+}
+
+fn empty_json(kind: &str) -> String {
+    format!("{{\"t\":\"{kind}\"}}")
+}
+
+fn str_element(text: &str) -> String {
+    format!("{},", str_json(text))
+}
+
+fn str_json(text: &str) -> String {
+    format!("{{\"t\":\"Str\",\"c\":{}}}", string(text))
+}
+
+fn span_element(class: &str, content: &str) -> String {
+    element(
+        "Span",
+        &format!("[{},{}]", attr("", &[class], &[]), array(content)),
+    )
+}
+
+fn link_element(content: &str, url: &str) -> String {
+    element(
+        "Link",
+        &format!(
+            "[{},{},[{},\"\"]]",
+            attr("", &[], &[]),
+            array(content),
+            string(url)
+        ),
+    )
+}
+
+/// Rendered values as a JSON array, their trailing comma dropped
+fn array(values: &str) -> String {
+    format!("[{}]", values.strip_suffix(',').unwrap_or(values))
+}
+
+/// Identifier, classes and key-value pairs of an element
+fn attr(id: &str, classes: &[&str], attributes: &[(String, String)]) -> String {
+    let classes: Vec<String> = classes.iter().map(|class| string(class)).collect();
+    let attributes: Vec<String> = attributes
+        .iter()
+        .map(|(key, value)| format!("[{},{}]", string(key), string(value)))
+        .collect();
+    format!(
+        "[{},[{}],[{}]]",
+        string(id),
+        classes.join(","),
+        attributes.join(",")
+    )
+}
+
+/// Text as `Str` words separated by `Space`s, as Pandoc splits it
+fn words(text: &str) -> String {
+    let mut out = String::new();
+    let mut word = String::new();
+    let mut space = false;
+    for c in text.chars() {
+        if matches!(c, ' ' | '\t' | '\n') {
+            space = true;
+            continue;
+        }
+        if space {
+            if !word.is_empty() {
+                out.push_str(&str_element(&word));
+                word.clear();
+            }
+            out.push_str(&empty_element("Space"));
+            space = false;
+        }
+        word.push(c);
+    }
+    if !word.is_empty() {
+        out.push_str(&str_element(&word));
+    }
+    if space {
+        out.push_str(&empty_element("Space"));
+    }
+    out
+}
+
+/// `text` as a JSON string literal
+fn string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
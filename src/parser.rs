@@ -1,237 +1,694 @@
-use crate::lexer::{HrStyle, Token};
+use crate::diagnostics::Diagnostic;
+use crate::environments::{self, EnvType, EnvTypes, ParamKind, Params};
+use crate::lexer::{HrStyle, Span, Token};
+use crate::references::Target;
+use crate::toc::TocEntry;
+use std::borrow::Cow;
+use std::ops::Range;
+use std::sync::{Arc, LazyLock};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ListType {
     Normal,
 }
 
-#[derive(Debug)]
-pub enum EnvType {
-    Definition,
-    Theorem,
-    Corollary,
-    Lemma,
-    Remark,
-    Example,
-    Exercise,
-    Fold,
-    Conceal,
-}
-
-#[derive(Debug)]
-pub enum Node {
+/// Document tree borrowing its text from the parsed input
+#[derive(Debug, Clone)]
+pub enum Node<'a> {
     Header {
         level: u8,
-        children: Vec<Node>,
+        /// Trailing `{#label}`
+        label: Option<Label<'a>>,
+        /// Anchor, the label or a slug generated by `headings::assign`
+        id: Option<Cow<'a, str>>,
+        /// Section number, empty until `headings::assign` runs
+        number: Vec<u32>,
+        children: Vec<Node<'a>>,
+    },
+    Bold(Vec<Node<'a>>),
+    Italic(Vec<Node<'a>>),
+    Striked(Vec<Node<'a>>),
+    Underline {
+        children: Vec<Node<'a>>,
+        /// Opening delimiter
+        span: Span,
+    },
+    Highlighted {
+        children: Vec<Node<'a>>,
+        /// Opening delimiter
+        span: Span,
     },
-    Bold(Vec<Node>),
-    Italic(Vec<Node>),
-    Striked(Vec<Node>),
-    Underline(Vec<Node>),
-    Highlighted(Vec<Node>),
     Link {
-        url: String,
-        childen: Vec<Node>,
+        url: Cow<'a, str>,
+        childen: Vec<Node<'a>>,
     },
     List {
         list_type: ListType,
-        children: Vec<Node>,
+        children: Vec<Node<'a>>,
     },
-    ListItem(Vec<Node>),
-    InlineCode(String),
+    ListItem(Vec<Node<'a>>),
+    InlineCode(Cow<'a, str>),
     CodeBlock {
-        language: Option<String>,
-        code: String,
+        language: Option<Cow<'a, str>>,
+        code: Cow<'a, str>,
+    },
+    /// ```` ```{=html} ```` block, markup passed through unescaped by the HTML backend
+    RawHtml(Cow<'a, str>),
+    InlineMath {
+        math: Cow<'a, str>,
+        span: Span,
+    },
+    DisplayMath {
+        math: Cow<'a, str>,
+        span: Span,
+        /// `\] {#label}`, which also serves as anchor
+        label: Option<Label<'a>>,
+        /// Set by `numbering::assign` on labelled equations
+        number: Vec<u32>,
     },
-    InlineMath(String),
-    DisplayMath(String),
     Env {
-        environment_type: EnvType,
-        environment_arg: Option<Vec<Node>>,
-        children: Vec<Node>,
+        environment_type: Arc<EnvType>,
+        /// Name following the `%`, which may be one of the type's aliases
+        name: Cow<'a, str>,
+        /// `%name` opening it
+        span: Span,
+        /// Title, what follows the parameters on the `%name` line
+        environment_arg: Option<Vec<Node<'a>>>,
+        /// Leading `key=value` and flag options of the `%name` line
+        params: Params<'a>,
+        /// Statement a proof or other attached environment belongs to, set by
+        /// `references::resolve`
+        attached: Option<Target<'a>>,
+        /// Trailing `{#label}` of the title line
+        label: Option<Label<'a>>,
+        /// Anchor, the label or one set by `numbering::assign` on numbered environments
+        id: Option<Cow<'a, str>>,
+        /// Empty for unnumbered environments
+        number: Vec<u32>,
+        children: Vec<Node<'a>>,
     },
     NewLine,
-    Paragraph(Vec<Node>),
-    Text(String),
-    Hr(HrStyle),
-    NBSP,
+    Paragraph(Vec<Node<'a>>),
+    Text(Cow<'a, str>),
+    Hr {
+        style: HrStyle,
+        span: Span,
+    },
+    Nbsp,
+    /// `%toc` marker, filled by `toc::fill`
+    TableOfContents(Vec<TocEntry<'a>>),
+    /// `%include path`, replaced by the file's content by `include::expand`
+    Include {
+        path: Cow<'a, str>,
+        /// Added to the level of the file's headers, from `offset=n`
+        offset: i64,
+        span: Span,
+    },
+    /// `\ref{label}`, or `\eqref{label}` when `equation` is set
+    Ref {
+        label: Cow<'a, str>,
+        equation: bool,
+        span: Span,
+        /// Set by `references::resolve` when the label exists
+        target: Option<Target<'a>>,
+    },
 }
 
-pub struct Parser {
-    tokens: Vec<Token>,
+/// Name given to a header, environment or equation so that it can be referenced
+#[derive(Debug, Clone)]
+pub struct Label<'a> {
+    pub name: Cow<'a, str>,
+    pub span: Span,
 }
 
-impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens }
+impl Label<'_> {
+    pub fn into_owned(self) -> Label<'static> {
+        Label {
+            name: Cow::Owned(self.name.into_owned()),
+            span: self.span,
+        }
+    }
+}
+
+/// Node detached from the input, for callers that need to keep it around
+pub type OwnedNode = Node<'static>;
+
+impl<'a> Node<'a> {
+    /// Nested block or inline content, not counting an environment's title
+    pub fn children(&self) -> &[Node<'a>] {
+        match self {
+            Node::Header { children, .. }
+            | Node::Bold(children)
+            | Node::Italic(children)
+            | Node::Striked(children)
+            | Node::Underline { children, .. }
+            | Node::Highlighted { children, .. }
+            | Node::Link {
+                childen: children, ..
+            }
+            | Node::List { children, .. }
+            | Node::ListItem(children)
+            | Node::Env { children, .. }
+            | Node::Paragraph(children) => children,
+            _ => &[],
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Option<&mut Vec<Node<'a>>> {
+        match self {
+            Node::Header { children, .. }
+            | Node::Bold(children)
+            | Node::Italic(children)
+            | Node::Striked(children)
+            | Node::Underline { children, .. }
+            | Node::Highlighted { children, .. }
+            | Node::Link {
+                childen: children, ..
+            }
+            | Node::List { children, .. }
+            | Node::ListItem(children)
+            | Node::Env { children, .. }
+            | Node::Paragraph(children) => Some(children),
+            _ => None,
+        }
     }
 
-    pub fn preprocess(tokens: Vec<Token>) -> Vec<Token> {
-        match tokens.get(0) {
-            Some(Token::Header(_)) => tokens,
-            _ => [vec![Token::NewLine; 2], tokens].concat(),
+    /// Copies every borrowed string so that the node no longer depends on the input
+    pub fn into_owned(self) -> OwnedNode {
+        fn owned(text: Cow<str>) -> Cow<'static, str> {
+            Cow::Owned(text.into_owned())
+        }
+        fn all(nodes: Vec<Node>) -> Vec<OwnedNode> {
+            nodes.into_iter().map(Node::into_owned).collect()
         }
+
+        match self {
+            Node::Header {
+                level,
+                label,
+                id,
+                number,
+                children,
+            } => Node::Header {
+                level,
+                label: label.map(Label::into_owned),
+                id: id.map(owned),
+                number,
+                children: all(children),
+            },
+            Node::Bold(children) => Node::Bold(all(children)),
+            Node::Italic(children) => Node::Italic(all(children)),
+            Node::Striked(children) => Node::Striked(all(children)),
+            Node::Underline { children, span } => Node::Underline {
+                children: all(children),
+                span,
+            },
+            Node::Highlighted { children, span } => Node::Highlighted {
+                children: all(children),
+                span,
+            },
+            Node::Link { url, childen } => Node::Link {
+                url: owned(url),
+                childen: all(childen),
+            },
+            Node::List {
+                list_type,
+                children,
+            } => Node::List {
+                list_type,
+                children: all(children),
+            },
+            Node::ListItem(children) => Node::ListItem(all(children)),
+            Node::InlineCode(code) => Node::InlineCode(owned(code)),
+            Node::CodeBlock { language, code } => Node::CodeBlock {
+                language: language.map(owned),
+                code: owned(code),
+            },
+            Node::RawHtml(html) => Node::RawHtml(owned(html)),
+            Node::InlineMath { math, span } => Node::InlineMath {
+                math: owned(math),
+                span,
+            },
+            Node::DisplayMath {
+                math,
+                span,
+                label,
+                number,
+            } => Node::DisplayMath {
+                math: owned(math),
+                span,
+                label: label.map(Label::into_owned),
+                number,
+            },
+            Node::Env {
+                environment_type,
+                name,
+                span,
+                environment_arg,
+                params,
+                attached,
+                label,
+                id,
+                number,
+                children,
+            } => Node::Env {
+                environment_type,
+                name: owned(name),
+                span,
+                environment_arg: environment_arg.map(all),
+                params: params.into_owned(),
+                attached: attached.map(Target::into_owned),
+                label: label.map(Label::into_owned),
+                id: id.map(owned),
+                number,
+                children: all(children),
+            },
+            Node::NewLine => Node::NewLine,
+            Node::Paragraph(children) => Node::Paragraph(all(children)),
+            Node::Text(text) => Node::Text(owned(text)),
+            Node::Hr { style, span } => Node::Hr { style, span },
+            Node::Nbsp => Node::Nbsp,
+            Node::TableOfContents(entries) => {
+                Node::TableOfContents(entries.into_iter().map(TocEntry::into_owned).collect())
+            }
+            Node::Include { path, offset, span } => Node::Include {
+                path: owned(path),
+                offset,
+                span,
+            },
+            Node::Ref {
+                label,
+                equation,
+                span,
+                target,
+            } => Node::Ref {
+                label: owned(label),
+                equation,
+                span,
+                target: target.map(Target::into_owned),
+            },
+        }
+    }
+}
+
+/// Content of a code block whose first line is `{=html}`
+fn raw_html<'a>(code: &Cow<'a, str>) -> Option<Cow<'a, str>> {
+    let rest = code.strip_prefix("{=html}")?;
+    let rest = rest
+        .strip_prefix("\r\n")
+        .or_else(|| rest.strip_prefix('\n'))?;
+    let start = code.len() - rest.len();
+    Some(match code {
+        Cow::Borrowed(code) => Cow::Borrowed(&code[start..]),
+        Cow::Owned(code) => Cow::Owned(code[start..].to_string()),
+    })
+}
+
+/// Single-line `%name` commands, which have no body nor closing `%`
+fn is_directive(name: &str) -> bool {
+    matches!(name, "toc" | "include")
+}
+
+/// Options of `%include`, parsed like an environment's
+static INCLUDE: LazyLock<EnvType> =
+    LazyLock::new(|| EnvType::new("include").param("offset", ParamKind::Integer));
+
+/// Splits a trailing `{#label}` off some text, returning the text before it, the label's name
+/// and the label's byte range within `text`
+fn split_label(text: &str) -> Option<(&str, &str, Range<usize>)> {
+    let trimmed = text.trim_end();
+    let inner = trimmed.strip_suffix('}')?;
+    let start = inner.rfind("{#")?;
+    let name = &inner[start + 2..];
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return None;
     }
+    Some((text[..start].trim_end(), name, start..trimmed.len()))
+}
+
+fn is_param_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
 
-    pub fn parse(&mut self, parsing_list: bool) -> Vec<Node> {
-        let mut nodes: Vec<Node> = vec![];
+/// Yielded in place of the `NewLine` tokens a parser is asked to pretend it has seen
+static NEW_LINE: Token<'static> = Token::NewLine;
+
+pub struct Parser<'t, 'a> {
+    tokens: &'t [Token<'a>],
+    environments: &'t EnvTypes,
+    /// Source range of each token, or empty when unknown
+    spans: &'t [Span],
+    pos: usize,
+    /// Virtual `NewLine` tokens to yield before `tokens[pos]`
+    pending_newlines: usize,
+    diagnostics: Vec<Diagnostic>,
+    /// Number of parsers this one is nested in
+    depth: usize,
+    max_depth: Option<usize>,
+}
+
+impl<'t, 'a> Parser<'t, 'a> {
+    pub fn new(tokens: &'t [Token<'a>]) -> Self {
+        Parser {
+            tokens,
+            environments: &environments::BUILTIN,
+            spans: &[],
+            pos: 0,
+            pending_newlines: 0,
+            diagnostics: vec![],
+            depth: 0,
+            max_depth: None,
+        }
+    }
+
+    /// Parser over a whole file, whose first line opens a paragraph unless it is a header
+    ///
+    /// `spans` are the ones from `Lexer::tokenize_with_spans`, or empty.
+    pub fn document(tokens: &'t [Token<'a>], spans: &'t [Span]) -> Self {
+        Parser {
+            tokens,
+            environments: &environments::BUILTIN,
+            spans,
+            pos: 0,
+            pending_newlines: match tokens.first() {
+                Some(Token::Header(_)) => 0,
+                _ => 2,
+            },
+            diagnostics: vec![],
+            depth: 0,
+            max_depth: None,
+        }
+    }
+
+    /// Recognizes `environments` instead of the built-in environment types
+    pub fn with_environments(mut self, environments: &'t EnvTypes) -> Self {
+        self.environments = environments;
+        self
+    }
+
+    /// Leaves out, with an error, content nested more than `max_depth` levels deep, so that
+    /// untrusted input cannot overflow the stack
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Parser over some of this parser's tokens
+    fn sub(&self, range: Range<usize>) -> Parser<'t, 'a> {
+        Parser {
+            tokens: &self.tokens[range.clone()],
+            environments: self.environments,
+            spans: self.spans.get(range).unwrap_or_default(),
+            pos: 0,
+            pending_newlines: 0,
+            diagnostics: vec![],
+            depth: self.depth + 1,
+            max_depth: self.max_depth,
+        }
+    }
+
+    /// Parses some of this parser's tokens, keeping the problems found there
+    fn parse_sub(&mut self, range: Range<usize>) -> Vec<Node<'a>> {
+        let mut parser = self.sub(range);
+        let nodes = parser.parse(false);
+        self.diagnostics.append(&mut parser.diagnostics);
+        nodes
+    }
+
+    /// Problems found so far, such as invalid environment parameters
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn into_diagnostics(self) -> Vec<Diagnostic> {
+        self.diagnostics
+    }
+
+    fn span(&self, index: usize) -> Span {
+        self.spans.get(index).copied().unwrap_or_default()
+    }
+
+    pub fn parse(&mut self, parsing_list: bool) -> Vec<Node<'a>> {
+        let mut nodes: Vec<Node<'a>> = vec![];
+        if let Some(max_depth) = self.max_depth.filter(|&max_depth| self.depth > max_depth) {
+            if !self.tokens.is_empty() {
+                self.diagnostics.push(Diagnostic::error(
+                    format!("content nested more than {max_depth} levels deep is left out"),
+                    self.span(0),
+                ));
+            }
+            self.pos = self.tokens.len();
+            return nodes;
+        }
 
         'parse: while let Some(current) = self.advance() {
             match current {
                 Token::ListItem(indent_level) if !parsing_list => {
-                    let mut consumed = vec![Token::ListItem(indent_level)];
-                    consumed.extend(self.advance_until_included(&Token::NewLine));
+                    let start = self.pos - 1;
+                    self.advance_until_included(&Token::NewLine);
 
-                    while let Some(tok) = self.tokens.get(0) {
+                    while let Some(tok) = self.peek(0) {
                         match tok {
                             // What’s allowed at line start during list parsing
-                            Token::Indent(level) if (level - 2) >= indent_level => (),
-                            Token::ListItem(level) if level >= &indent_level => (),
+                            Token::Indent(level) if level.saturating_sub(2) >= *indent_level => (),
+                            Token::ListItem(level) if level >= indent_level => (),
                             Token::NewLine => (),
                             _ => break,
                         }
-                        consumed.extend(self.advance_until_included(&Token::NewLine));
+                        self.advance_until_included(&Token::NewLine);
                     }
+                    let consumed = start..self.pos;
 
-                    self.tokens.insert(0, Token::NewLine);
-                    self.tokens.insert(0, Token::NewLine);
+                    self.pending_newlines += 2;
 
+                    let mut parser = self.sub(consumed);
+                    let children = parser.parse(true);
+                    self.diagnostics.append(&mut parser.diagnostics);
                     nodes.push(Node::List {
                         list_type: ListType::Normal,
-                        children: Parser::new(consumed).parse(true),
+                        children,
                     })
                 }
                 Token::ListItem(indent_level) if parsing_list => {
-                    let should_include_paragraph = if let Some(Token::Text(_)) = self.tokens.get(0)
-                    {
-                        true
-                    } else {
-                        false
-                    };
-                    let mut consumed = self.advance_until_included(&Token::NewLine);
+                    let should_include_paragraph = matches!(self.peek(0), Some(Token::Text(_)));
+                    let start = self.pos;
+                    self.advance_until_included(&Token::NewLine);
 
-                    while let Some(tok) = self.tokens.get(0) {
+                    while let Some(tok) = self.peek(0) {
                         match tok {
-                            Token::Indent(level) if (level - 2) >= indent_level => (),
-                            Token::ListItem(level) if level > &indent_level => (),
+                            Token::Indent(level) if level.saturating_sub(2) >= *indent_level => (),
+                            Token::ListItem(level) if level > indent_level => (),
                             Token::NewLine => (),
                             _ => break,
                         }
-                        consumed.extend(self.advance_until_included(&Token::NewLine));
+                        self.advance_until_included(&Token::NewLine);
                     }
 
+                    let mut parser = self.sub(start..self.pos);
                     if should_include_paragraph {
-                        consumed.insert(0, Token::NewLine);
-                        consumed.insert(0, Token::NewLine);
+                        parser.pending_newlines = 2;
                     }
-
-                    nodes.push(Node::ListItem(Parser::new(consumed).parse(false)));
+                    nodes.push(Node::ListItem(parser.parse(false)));
+                    self.diagnostics.append(&mut parser.diagnostics);
                 }
 
                 // Token::ListItem(indent_level) => nodes.push(Node::List{
                 //     list_type: ListType::Normal,
                 //     children: Parser::new(self.advance_until(&))
                 // })
-                Token::Header(level) => nodes.push(Node::Header {
-                    level,
-                    children: Parser::new(self.advance_until_and_stop_before(&Token::NewLine))
-                        .parse(false),
+                Token::Header(level) => {
+                    let line = self.advance_until_and_stop_before(&Token::NewLine);
+                    let (children, label) = self.parse_labelled(line);
+                    nodes.push(Node::Header {
+                        level: *level,
+                        label,
+                        id: None,
+                        number: vec![],
+                        children,
+                    })
+                }
+                Token::Bold => {
+                    let inner = self.advance_until(&Token::Bold);
+                    nodes.push(Node::Bold(self.parse_sub(inner)))
+                }
+                Token::Italic => {
+                    let inner = self.advance_until(&Token::Italic);
+                    nodes.push(Node::Italic(self.parse_sub(inner)))
+                }
+                Token::Striked => {
+                    let inner = self.advance_until(&Token::Striked);
+                    nodes.push(Node::Striked(self.parse_sub(inner)))
+                }
+                Token::Underline => {
+                    let span = self.span(self.pos - 1);
+                    let inner = self.advance_until(&Token::Underline);
+                    nodes.push(Node::Underline {
+                        children: self.parse_sub(inner),
+                        span,
+                    })
+                }
+                Token::Highlighted => {
+                    let span = self.span(self.pos - 1);
+                    let inner = self.advance_until(&Token::Highlighted);
+                    nodes.push(Node::Highlighted {
+                        children: self.parse_sub(inner),
+                        span,
+                    })
+                }
+                Token::Text(text) => nodes.push(Node::Text(Cow::Borrowed(text))),
+                Token::InlineMath(math) => nodes.push(Node::InlineMath {
+                    math: Cow::Borrowed(math),
+                    span: self.span(self.pos - 1),
                 }),
-                Token::Bold => nodes.push(Node::Bold(
-                    Parser::new(self.advance_until(&Token::Bold)).parse(false),
-                )),
-                Token::Italic => nodes.push(Node::Italic(
-                    Parser::new(self.advance_until(&Token::Italic)).parse(false),
-                )),
-                Token::Striked => nodes.push(Node::Striked(
-                    Parser::new(self.advance_until(&Token::Striked)).parse(false),
-                )),
-                Token::Underline => nodes.push(Node::Underline(
-                    Parser::new(self.advance_until(&Token::Underline)).parse(false),
-                )),
-                Token::Highlighted => nodes.push(Node::Highlighted(
-                    Parser::new(self.advance_until(&Token::Highlighted)).parse(false),
-                )),
-                Token::Text(text) => nodes.push(Node::Text(text.clone())),
-                Token::InlineMath(math) => nodes.push(Node::InlineMath(math.to_string())),
-                Token::DisplayMath(math) => nodes.push(Node::DisplayMath(math.to_string())),
-                Token::InlineCode(code) => nodes.push(Node::InlineCode(code.to_string())),
-                Token::CodeBlock(code) => nodes.push(Node::CodeBlock {
-                    language: None,
-                    code: code.to_string(),
+                Token::DisplayMath(math) => {
+                    let span = self.span(self.pos - 1);
+                    // `\] {#label}`
+                    let mut label = None;
+                    if let Some(Token::Text(text)) = self.peek(0) {
+                        if let Some(("", name, range)) = split_label(text.trim_start()) {
+                            let offset = text.len() - text.trim_start().len();
+                            label = Some(Label {
+                                name: Cow::Borrowed(name),
+                                span: self
+                                    .span(self.pos)
+                                    .within(offset + range.start..offset + range.end),
+                            });
+                            self.advance();
+                        }
+                    }
+                    nodes.push(Node::DisplayMath {
+                        math: Cow::Borrowed(math),
+                        span,
+                        label,
+                        number: vec![],
+                    })
+                }
+                Token::InlineCode(code) => nodes.push(Node::InlineCode(Cow::Borrowed(code))),
+                Token::CodeBlock(code) => nodes.push(match raw_html(code) {
+                    Some(html) => Node::RawHtml(html),
+                    None => Node::CodeBlock {
+                        language: None,
+                        code: code.clone(),
+                    },
                 }),
-                Token::EnvBegin(name) => {
-                    let env_type = match name.as_str() {
-                        "def" => EnvType::Definition,
-                        "thm" => EnvType::Theorem,
-                        "cor" => EnvType::Corollary,
-                        "lemma" => EnvType::Lemma,
-                        "rem" => EnvType::Remark,
-                        "eg" => EnvType::Example,
-                        "ex" => EnvType::Exercise,
-                        "fold" => EnvType::Fold,
-                        "conceal" => EnvType::Conceal,
-                        _ => continue 'parse,
-                    };
-
+                Token::Ref(label) | Token::EqRef(label) => nodes.push(Node::Ref {
+                    label: Cow::Borrowed(label),
+                    equation: matches!(current, Token::EqRef(_)),
+                    span: self.span(self.pos - 1),
+                    target: None,
+                }),
+                Token::EnvBegin(_, "include") => {
+                    let opener = self.pos - 1;
                     let line = self.advance_until(&Token::NewLine);
-                    let arg = if line.len() > 0 {
-                        Some(Parser::new(line).parse(false))
-                    } else {
-                        None
+                    let include = match self.tokens[line.clone()].first() {
+                        Some(Token::Text(text)) => {
+                            let origin = self.span(line.start);
+                            let (params, rest) = self.parse_params(&INCLUDE, text, origin);
+                            let start = text.len() - rest.len();
+                            let path = rest.trim_end();
+                            (!path.is_empty()).then(|| Node::Include {
+                                path: Cow::Borrowed(path),
+                                offset: params.integer("offset").unwrap_or(0),
+                                span: origin.within(start..start + path.len()),
+                            })
+                        }
+                        _ => None,
                     };
+                    match include {
+                        Some(include) => nodes.push(include),
+                        None => self.diagnostics.push(Diagnostic::error(
+                            "`%include` expects a path",
+                            self.span(opener),
+                        )),
+                    }
+                }
+                Token::EnvBegin(_, name) if is_directive(name) => {
+                    self.advance_until(&Token::NewLine);
+                    nodes.push(Node::TableOfContents(vec![]));
+                }
+                // A lone `%` inside text
+                Token::EnvBegin(_, "") => continue 'parse,
+                Token::EnvBegin(depth, name) => {
+                    let opener = self.pos - 1;
+                    let env_type = self.environments.resolve(name);
 
-                    let mut consumed: Vec<Token> = vec![];
-                    let mut count: i8 = 0;
-                    while !self.eof() && (!self.next_is(&Token::EnvEnd) || count != 0) {
-                        let current = self.tokens.remove(0);
-                        match current {
-                            Token::EnvBegin(_) => count += 1,
-                            Token::EnvEnd => count -= 1,
-                            _ => (),
+                    let line = self.advance_until(&Token::NewLine);
+                    let (mut arg, label) = self.parse_labelled(line.clone());
+                    let mut params = Params::default();
+                    if let (Some(Token::Text(_)), Some(Node::Text(Cow::Borrowed(text)))) =
+                        (self.tokens.get(line.start), arg.first_mut())
+                    {
+                        let origin = self.span(line.start);
+                        let rest;
+                        (params, rest) = self.parse_params(&env_type, text, origin);
+                        *text = rest;
+                        if rest.is_empty() {
+                            arg.remove(0);
                         }
-                        consumed.push(current);
                     }
-                    self.advance();
+
+                    let consumed = self.advance_to_env_end(*depth, name, opener);
 
                     nodes.push(Node::Env {
                         environment_type: env_type,
-                        environment_arg: arg,
-                        children: Parser::new(consumed).parse(false),
+                        name: Cow::Borrowed(name),
+                        span: self.span(opener),
+                        environment_arg: Some(arg).filter(|arg| !arg.is_empty()),
+                        params,
+                        attached: None,
+                        label,
+                        id: None,
+                        number: vec![],
+                        children: self.parse_sub(consumed),
                     })
                 }
                 Token::NewLine => {
                     if self.next_is(&Token::NewLine) {
                         self.advance();
-                        let mut consumed: Vec<Token> = vec![];
+                        let start = self.pos;
 
-                        while !self.eof()
-                            && !(self.next_is(&Token::NewLine)
-                                && self.next_n_is(&Token::NewLine, 1))
+                        while !(self.eof()
+                            || (self.next_is(&Token::NewLine)
+                                && self.next_n_is(&Token::NewLine, 1)))
                         {
-                            // Stop at one of [list, header, code block, hr, env] and cancel paragraph creation
-                            match self.tokens.get(0).unwrap() {
-                                Token::EnvBegin(_)
-                                | Token::EnvEnd
+                            // Stop at one of [list, header, code block, hr, env] and cancel paragraph
+                            // creation, leaving what came before to be parsed outside of one
+                            match self.peek(0).unwrap() {
+                                Token::EnvBegin(..)
+                                | Token::EnvEnd(_)
                                 | Token::ListItem(_)
                                 | Token::Header(_)
                                 | Token::CodeBlock(_)
                                 | Token::DisplayMath(_)
-                                | Token::Hr(_) => continue 'parse,
-                                _ => consumed.push(self.advance().unwrap()),
+                                | Token::Hr(_) => {
+                                    self.pos = start;
+                                    continue 'parse;
+                                }
+                                _ => self.pos += 1,
                             }
                         }
 
-                        if !consumed.is_empty() {
-                            nodes.push(Node::Paragraph(Parser::new(consumed).parse(false)));
+                        if start < self.pos {
+                            let children = self.parse_sub(start..self.pos);
+                            nodes.push(Node::Paragraph(children));
                         }
                     } else {
                         nodes.push(Node::NewLine)
                     }
                 }
-                Token::NBSP => nodes.push(Node::NBSP),
-                Token::Hr(style) => nodes.push(Node::Hr(style)),
+                Token::Nbsp => nodes.push(Node::Nbsp),
+                Token::Hr(style) => nodes.push(Node::Hr {
+                    style: style.clone(),
+                    span: self.span(self.pos - 1),
+                }),
                 Token::Indent(_) => (),
+                Token::EnvEnd(depth) => self.diagnostics.push(Diagnostic::error(
+                    format!("`{}` closes no environment", "%".repeat(*depth as usize)),
+                    self.span(self.pos - 1),
+                )),
                 _ => todo!(),
             }
         }
@@ -239,67 +696,172 @@ impl Parser {
         nodes
     }
 
-    fn advance(&mut self) -> Option<Token> {
-        if self.eof() {
-            None
+    /// Parses a line of inline content that may end with a `{#label}`
+    fn parse_labelled(&mut self, line: Range<usize>) -> (Vec<Node<'a>>, Option<Label<'a>>) {
+        if let Some(Token::Text(text)) = line.clone().last().map(|last| &self.tokens[last]) {
+            if let Some((rest, name, range)) = split_label(text) {
+                let mut children = self.parse_sub(line.start..line.end - 1);
+                if !rest.is_empty() {
+                    children.push(Node::Text(Cow::Borrowed(rest)));
+                }
+                let label = Label {
+                    name: Cow::Borrowed(name),
+                    span: self.span(line.end - 1).within(range),
+                };
+                return (children, Some(label));
+            }
+        }
+        (self.parse_sub(line), None)
+    }
+
+    /// Moves past the `%` line closing the environment opened at `tokens[opener]`, returning the
+    /// environment's content
+    ///
+    /// The closing line has as many `%` as the opening one, environments opened inside being
+    /// matched first. A closing line with another number of `%` ends the environment all the same,
+    /// so that no line is scanned more than once per level of nesting, and without any the
+    /// environment runs until the end; both are reported.
+    fn advance_to_env_end(&mut self, depth: u8, name: &str, opener: usize) -> Range<usize> {
+        let start = self.pos;
+        let env = format!("{}{name}", "%".repeat(depth as usize));
+        // Depths of the environments opened inside and not closed yet
+        let mut inner: Vec<u8> = vec![];
+        let mut mismatched = None;
+        for index in start..self.tokens.len() {
+            match self.tokens[index] {
+                Token::EnvBegin(inner_depth, name) if !name.is_empty() && !is_directive(name) => {
+                    inner.push(inner_depth)
+                }
+                Token::EnvEnd(end_depth) if inner.is_empty() => {
+                    if end_depth == depth {
+                        self.pos = index + 1;
+                        return start..index;
+                    }
+                    mismatched = Some(index);
+                    break;
+                }
+                Token::EnvEnd(end_depth) => match inner.iter().rposition(|&d| d == end_depth) {
+                    Some(position) => inner.truncate(position),
+                    None => {
+                        inner.pop();
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        match mismatched {
+            Some(index) => {
+                let Token::EnvEnd(end_depth) = self.tokens[index] else {
+                    unreachable!()
+                };
+                self.diagnostics.push(Diagnostic::error(
+                    format!(
+                        "`{}` closes `{env}`, which expects `{}`",
+                        "%".repeat(end_depth as usize),
+                        "%".repeat(depth as usize)
+                    ),
+                    self.span(index),
+                ));
+                self.pos = index + 1;
+                start..index
+            }
+            None => {
+                self.diagnostics.push(Diagnostic::error(
+                    format!("`{env}` is not closed"),
+                    self.span(opener),
+                ));
+                self.pos = self.tokens.len();
+                start..self.tokens.len()
+            }
+        }
+    }
+
+    /// Splits the leading options of a `%name` line off its first piece of text, read from
+    /// `origin`
+    ///
+    /// Options end at the first word that is neither `key=value` nor one of the type's flags.
+    /// Unknown keys and invalid values are reported and left out.
+    fn parse_params(
+        &mut self,
+        env_type: &EnvType,
+        text: &'a str,
+        origin: Span,
+    ) -> (Params<'a>, &'a str) {
+        let mut params = Params::default();
+        let mut rest = text.trim_start();
+        loop {
+            let folded = env_type.is_folded(&params);
+            let word = rest.split(char::is_whitespace).next().unwrap_or_default();
+            let (key, value) = match word.split_once('=') {
+                Some((key, value)) if is_param_name(key) => (key, Some(value)),
+                None if env_type.param_kind(word, folded) == Some(&ParamKind::Flag) => (word, None),
+                _ => break,
+            };
+            let start = text.len() - rest.len();
+            let span = origin.within(start..start + word.len());
+            rest = rest[word.len()..].trim_start();
+
+            match env_type.param_kind(key, folded) {
+                Some(kind) => match kind.parse(value) {
+                    Ok(value) => params.insert(Cow::Borrowed(key), value, span),
+                    Err(message) => self.diagnostics.push(Diagnostic::error(
+                        format!("invalid `{key}` parameter: {message}"),
+                        span,
+                    )),
+                },
+                None => self.diagnostics.push(Diagnostic::warning(
+                    format!("`{}` environments have no `{key}` parameter", env_type.name),
+                    span,
+                )),
+            }
+        }
+        (params, rest)
+    }
+
+    fn peek(&self, offset: usize) -> Option<&'t Token<'a>> {
+        if offset < self.pending_newlines {
+            Some(&NEW_LINE)
         } else {
-            Some(self.tokens.remove(0))
+            self.tokens.get(self.pos + offset - self.pending_newlines)
         }
     }
+    fn advance(&mut self) -> Option<&'t Token<'a>> {
+        if self.pending_newlines > 0 {
+            self.pending_newlines -= 1;
+            return Some(&NEW_LINE);
+        }
+        let token = self.tokens.get(self.pos)?;
+        self.pos += 1;
+        Some(token)
+    }
     fn next_is(&self, what: &Token) -> bool {
-        self.tokens.get(0) == Some(what)
+        self.peek(0) == Some(what)
     }
     fn next_n_is(&self, what: &Token, offset: usize) -> bool {
-        self.tokens.get(offset) == Some(what)
+        self.peek(offset) == Some(what)
     }
     fn eof(&self) -> bool {
-        self.tokens.is_empty()
+        self.pending_newlines == 0 && self.pos >= self.tokens.len()
     }
-    fn advance_until(&mut self, until: &Token) -> Vec<Token> {
-        let mut consumed: Vec<Token> = vec![];
-        while !self.eof() && !self.next_is(until) {
-            consumed.push(self.tokens.remove(0));
-        }
+    // The helpers below return ranges of `tokens` and are only reached once the virtual newlines
+    // have been consumed
+    fn advance_until(&mut self, until: &Token) -> Range<usize> {
+        let consumed = self.advance_until_and_stop_before(until);
         self.advance();
         consumed
     }
-    fn advance_until_included(&mut self, until: &Token) -> Vec<Token> {
-        let mut consumed: Vec<Token> = vec![];
-        while !self.eof() && !self.next_is(until) {
-            consumed.push(self.tokens.remove(0));
-        }
-
-        if let Some(next) = self.advance() {
-            consumed.push(next);
-        }
-        consumed
+    fn advance_until_included(&mut self, until: &Token) -> Range<usize> {
+        let start = self.pos;
+        self.advance_until_and_stop_before(until);
+        self.advance();
+        start..self.pos
     }
-    fn advance_until_and_stop_before(&mut self, until: &Token) -> Vec<Token> {
-        let mut consumed: Vec<Token> = vec![];
+    fn advance_until_and_stop_before(&mut self, until: &Token) -> Range<usize> {
+        let start = self.pos;
         while !self.eof() && !self.next_is(until) {
-            consumed.push(self.tokens.remove(0));
-        }
-
-        consumed
-    }
-    fn advance_until_tokens(&mut self, until: &Vec<Token>) -> Vec<Token> {
-        let mut consumed: Vec<Token> = vec![];
-
-        fn check_previous(consumed: &Vec<Token>, until: &Vec<Token>) -> bool {
-            consumed.len() >= until.len() && *until == consumed[(consumed.len() - until.len())..]
-        }
-
-        while !self.eof() && !check_previous(&consumed, &until) {
-            consumed.push(self.advance().unwrap());
-        }
-
-        if consumed.len() >= until.len() {
-            for u in until {
-                self.tokens.insert(0, u.clone());
-            }
-            consumed[..consumed.len() - until.len()].to_vec()
-        } else {
-            vec![]
+            self.pos += 1;
         }
+        start..self.pos
     }
 }
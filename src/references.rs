@@ -0,0 +1,151 @@
+use crate::diagnostics::Diagnostic;
+use crate::environments::EnvType;
+use crate::headings;
+use crate::parser::Node;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// What a `\ref` points to
+#[derive(Debug, Clone)]
+pub struct Target<'a> {
+    pub id: Cow<'a, str>,
+    pub number: Vec<u32>,
+    /// Type of the environment pointed to, `None` for headers and equations
+    pub environment_type: Option<Arc<EnvType>>,
+}
+
+impl Target<'_> {
+    pub fn into_owned(self) -> Target<'static> {
+        Target {
+            id: Cow::Owned(self.id.into_owned()),
+            number: self.number,
+            environment_type: self.environment_type,
+        }
+    }
+}
+
+/// Points every `\ref` at its label's anchor and number, and attaches proofs and the like to their
+/// statement
+///
+/// Runs once headers, environments and equations are numbered. Labels defined twice and references
+/// to unknown labels are reported.
+pub fn resolve<'a>(nodes: &mut [Node<'a>], diagnostics: &mut Vec<Diagnostic>) {
+    let mut targets: HashMap<String, Target<'a>> = HashMap::new();
+    headings::walk(nodes, &mut |node| {
+        let (label, target) = match node {
+            Node::Header {
+                label: Some(label),
+                id: Some(id),
+                number,
+                ..
+            } => (
+                label,
+                Target {
+                    id: id.clone(),
+                    number: number.clone(),
+                    environment_type: None,
+                },
+            ),
+            Node::Env {
+                environment_type,
+                label: Some(label),
+                id: Some(id),
+                number,
+                ..
+            } => (
+                label,
+                Target {
+                    id: id.clone(),
+                    number: number.clone(),
+                    environment_type: Some(environment_type.clone()),
+                },
+            ),
+            Node::DisplayMath {
+                label: Some(label),
+                number,
+                ..
+            } => {
+                let id = label.name.clone();
+                (
+                    label,
+                    Target {
+                        id,
+                        number: number.clone(),
+                        environment_type: None,
+                    },
+                )
+            }
+            _ => return,
+        };
+
+        if targets.contains_key(label.name.as_ref()) {
+            diagnostics.push(Diagnostic::error(
+                format!("label `{}` is defined more than once", label.name),
+                label.span,
+            ));
+        } else {
+            targets.insert(label.name.to_string(), target);
+        }
+    });
+
+    // Environments with an anchor, latest last
+    let mut statements: Vec<Target<'a>> = vec![];
+    headings::walk(nodes, &mut |node| match node {
+        Node::Ref {
+            label,
+            span,
+            target,
+            ..
+        } => match targets.get(label.as_ref()) {
+            Some(found) => *target = Some(found.clone()),
+            None => diagnostics.push(Diagnostic::warning(
+                format!("reference to undefined label `{label}`"),
+                *span,
+            )),
+        },
+        Node::Env {
+            environment_type,
+            params,
+            attached,
+            id,
+            number,
+            ..
+        } => {
+            if let Some(of) = params.text("of") {
+                match targets.get(of) {
+                    Some(found) => *attached = Some(found.clone()),
+                    None => diagnostics.push(Diagnostic::warning(
+                        format!("reference to undefined label `{of}`"),
+                        params.span("of").unwrap_or_default(),
+                    )),
+                }
+            } else if !environment_type.attach.is_empty() {
+                *attached = statements
+                    .iter()
+                    .rev()
+                    .find(|statement| {
+                        statement
+                            .environment_type
+                            .as_ref()
+                            .is_some_and(|statement_type| {
+                                environment_type
+                                    .attach
+                                    .iter()
+                                    .any(|name| statement_type.is_named(name))
+                            })
+                    })
+                    .cloned();
+            }
+
+            if let Some(id) = id {
+                statements.push(Target {
+                    id: id.clone(),
+                    number: number.clone(),
+                    environment_type: Some(environment_type.clone()),
+                });
+            }
+        }
+        _ => (),
+    });
+}
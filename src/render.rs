@@ -0,0 +1,255 @@
+use crate::document::Document;
+use crate::environments::Params;
+use crate::html::{self, Options};
+use crate::lexer::{HrStyle, Span};
+use crate::parser::{Label, ListType, Node};
+use crate::references::Target;
+use crate::toc::TocEntry;
+
+/// Output format, with a hook per kind of node
+///
+/// Every hook defaults to the markup of `HtmlRenderer` and renders nested nodes through `self`, so
+/// overriding one hook changes how that node renders wherever it appears. Other formats override
+/// all of them.
+pub trait Renderer {
+    fn options(&self) -> &Options;
+
+    /// Whole document, as a page, a filled template or just its body depending on the options
+    fn document(&mut self, document: &Document) -> String {
+        html::document(self, document)
+    }
+
+    fn nodes(&mut self, nodes: &[Node]) -> String {
+        nodes.iter().map(|node| self.node(node)).collect()
+    }
+
+    /// Hands `node` to the hook for its kind
+    fn node(&mut self, node: &Node) -> String {
+        match node {
+            Node::Header {
+                level,
+                id,
+                number,
+                children,
+                ..
+            } => self.header(*level, id.as_deref(), number, children),
+            Node::Bold(children) => self.bold(children),
+            Node::Italic(children) => self.italic(children),
+            Node::Striked(children) => self.striked(children),
+            Node::Underline { children, span } => self.underline(children, *span),
+            Node::Highlighted { children, span } => self.highlighted(children, *span),
+            Node::Link { url, childen } => self.link(url, childen),
+            Node::List {
+                list_type,
+                children,
+            } => self.list(list_type, children),
+            Node::ListItem(children) => self.list_item(children),
+            Node::InlineCode(code) => self.inline_code(code),
+            Node::CodeBlock { language, code } => self.code_block(language.as_deref(), code),
+            Node::RawHtml(html) => self.raw_html(html),
+            Node::InlineMath { math, span } => self.inline_math(math, *span),
+            Node::DisplayMath {
+                math,
+                span,
+                label,
+                number,
+            } => self.display_math(math, *span, label.as_ref(), number),
+            Node::Env { .. } => self.environment(node),
+            Node::NewLine => self.new_line(),
+            Node::Paragraph(children) => self.paragraph(children),
+            Node::Text(text) => self.text(text),
+            Node::Hr { style, span } => self.hr(style, *span),
+            Node::Nbsp => self.nbsp(),
+            Node::TableOfContents(entries) => self.table_of_contents(entries),
+            Node::Ref {
+                label,
+                equation,
+                target,
+                ..
+            } => self.reference(label, *equation, target.as_ref()),
+            // Left by callers that did not run `include::expand`
+            Node::Include { .. } => String::new(),
+        }
+    }
+
+    fn header(&mut self, level: u8, id: Option<&str>, number: &[u32], children: &[Node]) -> String {
+        html::header(self, level, id, number, children)
+    }
+
+    fn bold(&mut self, children: &[Node]) -> String {
+        html::wrap(self, "strong", children)
+    }
+
+    fn italic(&mut self, children: &[Node]) -> String {
+        html::wrap(self, "em", children)
+    }
+
+    fn striked(&mut self, children: &[Node]) -> String {
+        html::wrap(self, "s", children)
+    }
+
+    fn underline(&mut self, children: &[Node], _span: Span) -> String {
+        html::wrap(self, "u", children)
+    }
+
+    fn highlighted(&mut self, children: &[Node], _span: Span) -> String {
+        html::wrap(self, "mark", children)
+    }
+
+    fn link(&mut self, url: &str, children: &[Node]) -> String {
+        html::link(self, url, children)
+    }
+
+    fn list(&mut self, _list_type: &ListType, children: &[Node]) -> String {
+        html::wrap(self, "ul", children)
+    }
+
+    fn list_item(&mut self, children: &[Node]) -> String {
+        html::wrap(self, "li", children)
+    }
+
+    fn inline_code(&mut self, code: &str) -> String {
+        html::inline_code(code)
+    }
+
+    fn code_block(&mut self, language: Option<&str>, code: &str) -> String {
+        html::code_block(language, code)
+    }
+
+    /// Markup written by the author, the one thing the HTML backend does not escape
+    fn raw_html(&mut self, html: &str) -> String {
+        html::raw_html(self, html)
+    }
+
+    /// `span` is that of the whole formula, delimiters included
+    fn inline_math(&mut self, math: &str, _span: Span) -> String {
+        html::inline_math(math)
+    }
+
+    fn display_math(
+        &mut self,
+        math: &str,
+        _span: Span,
+        label: Option<&Label>,
+        number: &[u32],
+    ) -> String {
+        html::display_math(math, label, number, "")
+    }
+
+    /// `node` is a `Node::Env`
+    fn environment(&mut self, node: &Node) -> String {
+        html::environment(self, node)
+    }
+
+    /// Content of an environment ending with `mark`, such as a proof's ∎
+    fn nodes_with_qed(&mut self, nodes: &[Node], mark: &str) -> String {
+        html::nodes_with_qed(self, nodes, mark)
+    }
+
+    /// Nodes from a top-level header to the next one, followed by their solutions when these are
+    /// collected
+    fn chapter(&mut self, nodes: &[Node]) -> String {
+        self.nodes(nodes)
+    }
+
+    /// Solutions moved to the end of a chapter whose headers are at `level`
+    fn solutions(&mut self, solutions: &[&Node], level: u8) -> String {
+        html::solutions(self, solutions, level)
+    }
+
+    fn new_line(&mut self) -> String {
+        "<br/>".to_string()
+    }
+
+    fn paragraph(&mut self, children: &[Node]) -> String {
+        html::wrap(self, "p", children)
+    }
+
+    fn text(&mut self, text: &str) -> String {
+        html::escape_text(text)
+    }
+
+    fn hr(&mut self, style: &HrStyle, _span: Span) -> String {
+        html::hr(style)
+    }
+
+    fn nbsp(&mut self) -> String {
+        "&nbsp;".to_string()
+    }
+
+    fn table_of_contents(&mut self, entries: &[TocEntry]) -> String {
+        html::table_of_contents(self, entries)
+    }
+
+    /// `\ref{label}`, or `\eqref{label}` when `equation` is set
+    fn reference(&mut self, label: &str, equation: bool, target: Option<&Target>) -> String {
+        html::reference(label, equation, target)
+    }
+}
+
+/// Renders a document split into chapters at its top-level headers, each followed by the
+/// solutions written in it
+pub(crate) fn chapters<R: Renderer + ?Sized>(renderer: &mut R, nodes: &[Node]) -> String {
+    fn collect<'n, 'a>(nodes: &'n [Node<'a>], solutions: &mut Vec<&'n Node<'a>>) {
+        for node in nodes {
+            match node {
+                Node::Env {
+                    environment_type, ..
+                } if environment_type.solution => solutions.push(node),
+                _ => collect(node.children(), solutions),
+            }
+        }
+    }
+
+    let mut out = String::new();
+    let mut chapter_start = 0;
+    for index in 1..=nodes.len() {
+        if index == nodes.len()
+            || matches!(&nodes[index], Node::Header { number, .. } if number.len() == 1)
+        {
+            let chapter = &nodes[chapter_start..index];
+            out.push_str(&renderer.chapter(chapter));
+            let mut solutions = vec![];
+            collect(chapter, &mut solutions);
+            if !solutions.is_empty() {
+                let level = match chapter.first() {
+                    Some(Node::Header { level, .. }) => level.saturating_add(1).min(6),
+                    _ => 2,
+                };
+                out.push_str(&renderer.solutions(&solutions, level));
+            }
+            chapter_start = index;
+        }
+    }
+    out
+}
+
+/// Whether a concealing environment given `params` is selected by the `conceal_*` options
+pub(crate) fn is_concealed(params: &Params, options: &Options) -> bool {
+    let level = params.integer("level").unwrap_or(1);
+    options.conceal_level.is_none_or(|max| level <= max)
+        && (options.conceal_categories.is_empty()
+            || params
+                .text("categ")
+                .is_some_and(|categ| options.conceal_categories.iter().any(|c| c == categ)))
+}
+
+/// Anchor of an environment, made up from its statement's for solutions without one
+pub(crate) fn environment_id(node: &Node) -> Option<String> {
+    let Node::Env {
+        environment_type,
+        attached,
+        id,
+        ..
+    } = node
+    else {
+        return None;
+    };
+    match (id, attached) {
+        (Some(id), _) => Some(id.to_string()),
+        (None, Some(statement)) if environment_type.solution => {
+            Some(format!("{}-{}", statement.id, environment_type.name))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,113 @@
+use crate::environments::EnvType;
+use crate::headings;
+use crate::parser::Node;
+use std::borrow::Cow;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TocKind {
+    Section,
+    /// Numbered environment, listed under the section containing it
+    Environment(Arc<EnvType>),
+}
+
+/// Header or numbered environment in the table of contents, with the entries nested under it
+#[derive(Debug, Clone)]
+pub struct TocEntry<'a> {
+    pub kind: TocKind,
+    pub id: Cow<'a, str>,
+    pub number: Vec<u32>,
+    pub title: Vec<Node<'a>>,
+    pub children: Vec<TocEntry<'a>>,
+}
+
+impl TocEntry<'_> {
+    pub fn into_owned(self) -> TocEntry<'static> {
+        TocEntry {
+            kind: self.kind,
+            id: Cow::Owned(self.id.into_owned()),
+            number: self.number,
+            title: self.title.into_iter().map(Node::into_owned).collect(),
+            children: self
+                .children
+                .into_iter()
+                .map(TocEntry::into_owned)
+                .collect(),
+        }
+    }
+
+    /// Depth of the section, 1 for top-level ones; environments are deeper than any section
+    pub fn depth(&self) -> usize {
+        match self.kind {
+            TocKind::Section => self.number.len(),
+            TocKind::Environment(_) => usize::MAX,
+        }
+    }
+}
+
+/// Builds the section tree from headers and numbered environments, which must have gone through
+/// `headings::assign` and `numbering::assign`
+pub fn build<'a>(nodes: &[Node<'a>]) -> Vec<TocEntry<'a>> {
+    let mut entries: Vec<TocEntry> = vec![];
+    collect(nodes, &mut entries);
+
+    let mut roots: Vec<TocEntry> = vec![];
+    for entry in entries {
+        insert(&mut roots, entry);
+    }
+    roots
+}
+
+/// Fills every `%toc` marker with the document's table of contents
+pub fn fill(nodes: &mut [Node]) {
+    let entries = build(nodes);
+    headings::walk(nodes, &mut |node| {
+        if let Node::TableOfContents(marker) = node {
+            *marker = entries.clone();
+        }
+    });
+}
+
+fn collect<'a>(nodes: &[Node<'a>], entries: &mut Vec<TocEntry<'a>>) {
+    for node in nodes {
+        match node {
+            Node::Header {
+                id: Some(id),
+                number,
+                children,
+                ..
+            } => entries.push(TocEntry {
+                kind: TocKind::Section,
+                id: id.clone(),
+                number: number.clone(),
+                title: children.clone(),
+                children: vec![],
+            }),
+            Node::Env {
+                environment_type,
+                environment_arg,
+                id: Some(id),
+                number,
+                children,
+                ..
+            } if !number.is_empty() => {
+                entries.push(TocEntry {
+                    kind: TocKind::Environment(environment_type.clone()),
+                    id: id.clone(),
+                    number: number.clone(),
+                    title: environment_arg.clone().unwrap_or_default(),
+                    children: vec![],
+                });
+                collect(children, entries);
+            }
+            _ => collect(node.children(), entries),
+        }
+    }
+}
+
+fn insert<'a>(siblings: &mut Vec<TocEntry<'a>>, entry: TocEntry<'a>) {
+    match siblings.last_mut() {
+        Some(last) if last.depth() < entry.depth() => insert(&mut last.children, entry),
+        _ => siblings.push(entry),
+    }
+}
@@ -0,0 +1,1021 @@
+use crate::diagnostics::Diagnostic;
+use crate::document::Document;
+use crate::environments::{EnvStyle, EnvType};
+use crate::headings;
+use crate::html::{self, ConcealMode, Options, SolutionMode};
+use crate::lexer::{HrStyle, Span};
+use crate::parser::{Label, ListType, Node};
+use crate::references::Target;
+use crate::render::{self, environment_id, is_concealed, Renderer};
+use crate::toc::TocEntry;
+use std::sync::Arc;
+
+/// The Typst backend, rendering environments with a function generated for each type
+///
+/// Math is translated from LaTeX by `math`; formulas outside its subset are kept as raw text and
+/// reported in `diagnostics`.
+#[derive(Debug, Clone, Default)]
+pub struct TypstRenderer {
+    options: Options,
+    /// Inside a paragraph, the only place a line can be broken
+    in_paragraph: bool,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl TypstRenderer {
+    pub fn new(options: Options) -> Self {
+        Self {
+            options,
+            in_paragraph: false,
+            diagnostics: vec![],
+        }
+    }
+
+    /// Formulas that could not be translated so far
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn into_diagnostics(self) -> Vec<Diagnostic> {
+        self.diagnostics
+    }
+
+    fn lang(&self) -> &str {
+        self.options.lang.as_deref().unwrap_or("en")
+    }
+
+    /// `latex` translated, or as raw text with a diagnostic at `span`
+    fn math(&mut self, latex: &str, span: Span, block: bool) -> Result<String, String> {
+        math(latex).map_err(|message| {
+            self.diagnostics.push(Diagnostic::warning(
+                format!("cannot translate `{}` to Typst: {message}", latex.trim()),
+                span,
+            ));
+            format!(
+                "#raw({}{})",
+                if block { "block: true, " } else { "" },
+                string(latex.trim())
+            )
+        })
+    }
+
+    /// Renders an environment, citing the statement it is attached to when `link_statement` is
+    /// set or when it is given with `of`
+    fn environment_block(&mut self, node: &Node, link_statement: bool) -> String {
+        let Node::Env {
+            environment_type,
+            environment_arg,
+            params,
+            attached,
+            number,
+            children,
+            ..
+        } = node
+        else {
+            return self.node(node);
+        };
+        if environment_type.conceal
+            && is_concealed(params, &self.options)
+            && self.options.conceal == ConcealMode::Strip
+        {
+            return String::new();
+        }
+        let children = self.nodes(children);
+        if environment_type.label_for(self.lang()).is_none() {
+            // Folding and concealing blocks, which paper cannot do
+            return children;
+        }
+
+        let mut arguments = vec![];
+        if !number.is_empty() {
+            arguments.push(format!(
+                "number: {}",
+                string(&headings::format_number(number))
+            ));
+        }
+        let mut details = vec![];
+        if let Some(statement) = attached
+            .as_ref()
+            .filter(|_| link_statement || params.text("of").is_some())
+        {
+            details.push(format!(
+                "#link(<{}>)[{}]",
+                statement.id,
+                statement_name(statement, self.lang())
+            ));
+        }
+        if let Some(arg) = environment_arg {
+            details.push(self.nodes(arg));
+        }
+        if !details.is_empty() {
+            arguments.push(format!("title: [{}]", details.join(", ")));
+        }
+        let label = match environment_id(node) {
+            Some(id) => format!(" <{id}>"),
+            None => String::new(),
+        };
+        format!(
+            "#{}({})[{}]{label}\n\n",
+            function_name(environment_type),
+            arguments.join(", "),
+            children.trim_end()
+        )
+    }
+}
+
+impl Renderer for TypstRenderer {
+    fn options(&self) -> &Options {
+        &self.options
+    }
+
+    fn document(&mut self, document: &Document) -> String {
+        let body = match self.options.solutions {
+            SolutionMode::Collect => render::chapters(self, &document.nodes),
+            _ => self.nodes(&document.nodes),
+        };
+        if !self.options.standalone {
+            return body;
+        }
+
+        let metadata = &document.metadata;
+        let mut preamble = String::new();
+        if let Some(title) = metadata.title() {
+            preamble.push_str(&format!("#set document(title: {})\n", string(title)));
+        }
+        if let Some(lang) = metadata.lang() {
+            preamble.push_str(&format!("#set text(lang: {})\n", string(lang)));
+        }
+        if self.options.number_sections {
+            preamble.push_str("#set heading(numbering: \"1.1\")\n");
+        }
+        preamble.push_str(&functions(&document.nodes, self.lang()));
+        if let Some(title) = metadata.title() {
+            preamble.push_str(&format!(
+                "\n#align(center, text(size: 1.5em, strong({})))\n",
+                string(title)
+            ));
+        }
+        format!("{preamble}\n{body}")
+    }
+
+    fn header(
+        &mut self,
+        level: u8,
+        id: Option<&str>,
+        _number: &[u32],
+        children: &[Node],
+    ) -> String {
+        let label = match id {
+            Some(id) => format!(" <{id}>"),
+            None => String::new(),
+        };
+        format!(
+            "{} {}{label}\n\n",
+            "=".repeat(level.max(1) as usize),
+            self.nodes(children).trim()
+        )
+    }
+
+    fn bold(&mut self, children: &[Node]) -> String {
+        format!("#strong[{}]", self.nodes(children))
+    }
+
+    fn italic(&mut self, children: &[Node]) -> String {
+        format!("#emph[{}]", self.nodes(children))
+    }
+
+    fn striked(&mut self, children: &[Node]) -> String {
+        format!("#strike[{}]", self.nodes(children))
+    }
+
+    fn underline(&mut self, children: &[Node], _span: Span) -> String {
+        format!("#underline[{}]", self.nodes(children))
+    }
+
+    fn highlighted(&mut self, children: &[Node], _span: Span) -> String {
+        format!("#highlight[{}]", self.nodes(children))
+    }
+
+    fn link(&mut self, url: &str, children: &[Node]) -> String {
+        if self.options.safe && !html::is_safe_url(url) {
+            return self.nodes(children);
+        }
+        format!("#link({})[{}]", string(url), self.nodes(children))
+    }
+
+    fn list(&mut self, list_type: &ListType, children: &[Node]) -> String {
+        let function = match list_type {
+            ListType::Normal => "list",
+        };
+        format!("#{function}{}\n\n", self.nodes(children))
+    }
+
+    fn list_item(&mut self, children: &[Node]) -> String {
+        format!("[{}]", self.nodes(children).trim())
+    }
+
+    fn inline_code(&mut self, code: &str) -> String {
+        format!("#raw({})", string(code))
+    }
+
+    fn code_block(&mut self, language: Option<&str>, code: &str) -> String {
+        let lang = match language {
+            Some(language) => format!("lang: {}, ", string(language)),
+            None => String::new(),
+        };
+        format!("#raw(block: true, {lang}{})\n\n", string(code))
+    }
+
+    fn raw_html(&mut self, _html: &str) -> String {
+        String::new()
+    }
+
+    fn inline_math(&mut self, math: &str, span: Span) -> String {
+        match self.math(math, span, false) {
+            Ok(math) => format!("${math}$"),
+            Err(raw) => raw,
+        }
+    }
+
+    fn display_math(
+        &mut self,
+        math: &str,
+        span: Span,
+        label: Option<&Label>,
+        number: &[u32],
+    ) -> String {
+        let math = match self.math(math, span, true) {
+            Ok(math) => math,
+            Err(raw) => return format!("{raw}\n\n"),
+        };
+        match label {
+            // A numbering ignoring Typst's counter keeps the number `numbering::assign` gave
+            Some(label) if !number.is_empty() => format!(
+                "#[\n#set math.equation(numbering: _ => {})\n$ {math} $ <{}>\n]\n\n",
+                string(&format!("({})", headings::format_number(number))),
+                label.name
+            ),
+            _ => format!("$ {math} $\n\n"),
+        }
+    }
+
+    fn environment(&mut self, node: &Node) -> String {
+        match node {
+            Node::Env {
+                environment_type, ..
+            } if environment_type.solution && self.options.solutions != SolutionMode::Inline => {
+                match (self.options.solutions, environment_id(node)) {
+                    (SolutionMode::Collect, Some(id)) => format!(
+                        "#align(right, link(<{id}>)[{}])\n\n",
+                        escape(
+                            environment_type
+                                .label_for(self.lang())
+                                .unwrap_or(&environment_type.name)
+                        )
+                    ),
+                    _ => String::new(),
+                }
+            }
+            _ => self.environment_block(node, false),
+        }
+    }
+
+    /// The functions generated for types with a QED mark add it themselves
+    fn nodes_with_qed(&mut self, nodes: &[Node], _mark: &str) -> String {
+        self.nodes(nodes)
+    }
+
+    fn solutions(&mut self, solutions: &[&Node], level: u8) -> String {
+        let mut out =
+            format!("#heading(level: {level}, numbering: none, outlined: false)[Solutions]\n\n");
+        for solution in solutions {
+            out.push_str(&self.environment_block(solution, true));
+        }
+        out
+    }
+
+    fn new_line(&mut self) -> String {
+        if self.in_paragraph {
+            " \\\n".to_string()
+        } else {
+            "\n".to_string()
+        }
+    }
+
+    fn paragraph(&mut self, children: &[Node]) -> String {
+        let start = children
+            .iter()
+            .position(|node| !matches!(node, Node::NewLine))
+            .unwrap_or(children.len());
+        let end = children
+            .iter()
+            .rposition(|node| !matches!(node, Node::NewLine))
+            .map_or(start, |last| last + 1);
+        let in_paragraph = std::mem::replace(&mut self.in_paragraph, true);
+        let text = self.nodes(&children[start..end]);
+        self.in_paragraph = in_paragraph;
+        format!("{}\n\n", text.trim())
+    }
+
+    fn text(&mut self, text: &str) -> String {
+        escape(text)
+    }
+
+    fn hr(&mut self, style: &HrStyle, _span: Span) -> String {
+        let line = match style {
+            HrStyle::Normal => "line(length: 100%)",
+            HrStyle::Dashed => "line(length: 100%, stroke: (dash: \"dashed\"))",
+            HrStyle::Dotted => "line(length: 100%, stroke: (dash: \"dotted\"))",
+            HrStyle::Sawtooth => "align(center, text(fill: gray, \"∨\" * 40))",
+        };
+        format!("#{line}\n\n")
+    }
+
+    fn nbsp(&mut self) -> String {
+        "~".to_string()
+    }
+
+    fn table_of_contents(&mut self, _entries: &[TocEntry]) -> String {
+        "#outline()\n\n".to_string()
+    }
+
+    fn reference(&mut self, _label: &str, equation: bool, target: Option<&Target>) -> String {
+        match target {
+            Some(target) => {
+                let number = headings::format_number(&target.number);
+                let number = if equation {
+                    format!("({number})")
+                } else {
+                    number
+                };
+                format!("#link(<{}>)[{number}]", target.id)
+            }
+            None => "#strong[??]".to_string(),
+        }
+    }
+}
+
+/// Renders a document to Typst, along with the formulas that could not be translated
+pub fn render(document: &Document, options: &Options) -> (String, Vec<Diagnostic>) {
+    let options = Options {
+        lang: document
+            .metadata
+            .lang()
+            .map(str::to_string)
+            .or_else(|| options.lang.clone()),
+        ..options.clone()
+    };
+    let mut renderer = TypstRenderer::new(options);
+    let typst = renderer.document(document);
+    (typst, renderer.into_diagnostics())
+}
+
+/// "Théorème 2.3", or just the number for sections
+fn statement_name(target: &Target, lang: &str) -> String {
+    let number = headings::format_number(&target.number);
+    match target
+        .environment_type
+        .as_ref()
+        .and_then(|environment_type| environment_type.label_for(lang))
+    {
+        Some(label) if number.is_empty() => escape(label),
+        Some(label) => format!("{} {number}", escape(label)),
+        None => number,
+    }
+}
+
+/// Function rendering environments of a type, prefixed so as not to shadow Typst's own
+fn function_name(environment_type: &EnvType) -> String {
+    format!("env-{}", environment_type.name)
+}
+
+/// Definitions of the functions for the labelled environment types used in `nodes`
+///
+/// Each takes the environment's content, its `number` as a string and its `title` as content,
+/// and shows them in the type's style: statements in italics, remarks with a lighter title.
+fn functions(nodes: &[Node], lang: &str) -> String {
+    fn collect(nodes: &[Node], types: &mut Vec<Arc<EnvType>>) {
+        for node in nodes {
+            if let Node::Env {
+                environment_type, ..
+            } = node
+            {
+                if !types.iter().any(|t| t.name == environment_type.name) {
+                    types.push(environment_type.clone());
+                }
+            }
+            collect(node.children(), types);
+        }
+    }
+
+    let mut types = vec![];
+    collect(nodes, &mut types);
+    let mut definitions = String::new();
+    for environment_type in &types {
+        let Some(label) = environment_type.label_for(lang) else {
+            continue;
+        };
+        let heading = format!(
+            "{}[{}#if number != none [ #number]]#if title != none [ (#title)].",
+            match environment_type.style {
+                EnvStyle::Remark => "emph",
+                EnvStyle::Plain | EnvStyle::Definition => "strong",
+            },
+            escape(label)
+        );
+        let body = match environment_type.style {
+            EnvStyle::Plain => "emph(body)",
+            EnvStyle::Definition | EnvStyle::Remark => "body",
+        };
+        let qed = match &environment_type.qed {
+            Some(mark) => format!("#h(1fr){}", escape(mark)),
+            None => String::new(),
+        };
+        definitions.push_str(&format!(
+            "#let {}(number: none, title: none, body) = block(width: 100%)[#{heading} #{body}{qed}]\n",
+            function_name(environment_type)
+        ));
+    }
+    definitions
+}
+
+/// `text` with the characters that have a meaning in Typst markup escaped
+///
+/// This includes the `-`, `+`, `=` and `1.` that start list items and headings, as any text may
+/// end up opening a line.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let line = text.trim_start();
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    // The `.` of a leading `1.`
+    let dot =
+        (digits > 0 && line[digits..].starts_with('.')).then(|| text.len() - line.len() + digits);
+    for (index, c) in text.char_indices() {
+        if Some(index) == dot {
+            escaped.push('\\');
+        }
+        if matches!(
+            c,
+            '\\' | '#'
+                | '*'
+                | '_'
+                | '`'
+                | '$'
+                | '<'
+                | '>'
+                | '@'
+                | '['
+                | ']'
+                | '~'
+                | '='
+                | '-'
+                | '+'
+                | '/'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// `text` as a Typst string literal
+fn string(text: &str) -> String {
+    let mut literal = String::with_capacity(text.len() + 2);
+    literal.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Translates LaTeX math to Typst math, for the subset documented in `syntax.md`
+///
+/// The error names the first construct outside of it.
+pub fn math(latex: &str) -> Result<String, String> {
+    let mut translator = Translator {
+        chars: latex.chars().collect(),
+        pos: 0,
+        call_depth: 0,
+        depth: 0,
+    };
+    let out = translator.sequence(None, Rows::None)?;
+    if translator.pos < translator.chars.len() {
+        return Err("`\\end` without `\\begin`".to_string());
+    }
+    Ok(out.trim().to_string())
+}
+
+/// How `&` and `\\` separate the cells of the environment being translated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rows {
+    /// Kept as alignment points and line breaks
+    None,
+    /// Argument separators of `mat`
+    Matrix,
+    /// Argument separators of `cases`, `&` staying an alignment point
+    Cases,
+}
+
+/// Deepest nesting of groups and commands translated, past which a formula is kept as written
+/// rather than overflowing the stack
+const MAX_MATH_DEPTH: usize = 64;
+
+struct Translator {
+    chars: Vec<char>,
+    pos: usize,
+    /// Number of Typst function calls being written, inside which commas separate arguments
+    call_depth: usize,
+    /// Number of groups and commands being translated, bounded by `MAX_MATH_DEPTH`
+    depth: usize,
+}
+
+impl Translator {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Runs `translate` one level deeper, failing past `MAX_MATH_DEPTH`
+    fn nested(
+        &mut self,
+        translate: impl FnOnce(&mut Self) -> Result<String, String>,
+    ) -> Result<String, String> {
+        if self.depth >= MAX_MATH_DEPTH {
+            return Err(format!(
+                "math nested more than {MAX_MATH_DEPTH} levels deep"
+            ));
+        }
+        self.depth += 1;
+        let out = translate(self);
+        self.depth -= 1;
+        out
+    }
+
+    /// Translates up to `end`, consumed, or to the end of the input
+    fn sequence(&mut self, end: Option<char>, rows: Rows) -> Result<String, String> {
+        self.nested(|translator| translator.items(end, rows))
+    }
+
+    fn items(&mut self, end: Option<char>, rows: Rows) -> Result<String, String> {
+        let mut out = String::new();
+        loop {
+            let Some(c) = self.peek() else {
+                return match end {
+                    Some(end) => Err(format!("missing `{end}`")),
+                    None => Ok(out),
+                };
+            };
+            self.pos += 1;
+            match c {
+                _ if Some(c) == end => return Ok(out),
+                '}' => return Err("unbalanced `}`".to_string()),
+                '{' => {
+                    let group = self.sequence(Some('}'), Rows::None)?;
+                    push(&mut out, &group);
+                }
+                '^' | '_' => {
+                    let argument = self.argument()?;
+                    out.push(c);
+                    out.push_str(&script(&argument));
+                }
+                '\\' if self.peek() == Some('\\') => {
+                    self.pos += 1;
+                    out.push_str(match rows {
+                        Rows::None => " \\ ",
+                        Rows::Matrix => "; ",
+                        Rows::Cases => ", ",
+                    });
+                }
+                '\\' if self.peek() == Some('e') && self.next_is("end{") => {
+                    self.pos -= 1;
+                    return match end {
+                        None => Ok(out),
+                        Some(end) => Err(format!("missing `{end}`")),
+                    };
+                }
+                '\\' => {
+                    let command = self.command()?;
+                    push(&mut out, &command);
+                }
+                '&' if rows == Rows::Matrix => out.push_str(", "),
+                '&' => out.push_str(" & "),
+                c if c.is_whitespace() => (),
+                c if c.is_ascii_digit() => {
+                    let mut number = c.to_string();
+                    while let Some(c) = self.peek().filter(|c| c.is_ascii_digit() || *c == '.') {
+                        number.push(c);
+                        self.pos += 1;
+                    }
+                    push(&mut out, &number);
+                }
+                c => push(&mut out, &self.symbol(c)),
+            }
+        }
+    }
+
+    fn next_is(&self, text: &str) -> bool {
+        text.chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    /// A single character, group or command, as the argument of a command or script
+    fn argument(&mut self) -> Result<String, String> {
+        self.skip_spaces();
+        let Some(c) = self.peek() else {
+            return Err("missing argument".to_string());
+        };
+        self.pos += 1;
+        match c {
+            '{' => self.sequence(Some('}'), Rows::None),
+            '\\' => self.command(),
+            c => Ok(self.symbol(c)),
+        }
+    }
+
+    /// An argument written inside a Typst call, where commas must be escaped
+    fn call_argument(&mut self) -> Result<String, String> {
+        self.call_depth += 1;
+        let argument = self.argument();
+        self.call_depth -= 1;
+        argument
+    }
+
+    /// `{text}` read verbatim, for `\text` and the like
+    fn text_argument(&mut self) -> Result<String, String> {
+        self.skip_spaces();
+        if self.peek() != Some('{') {
+            return Err("expected `{`".to_string());
+        }
+        self.pos += 1;
+        let start = self.pos;
+        let mut depth = 0;
+        while let Some(c) = self.peek() {
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => {
+                    let text: String = self.chars[start..self.pos].iter().collect();
+                    self.pos += 1;
+                    return Ok(text);
+                }
+                '}' => depth -= 1,
+                _ => (),
+            }
+            self.pos += 1;
+        }
+        Err("missing `}`".to_string())
+    }
+
+    fn symbol(&self, c: char) -> String {
+        match c {
+            ',' | ';' if self.call_depth > 0 => format!("\\{c}"),
+            '/' | '"' | '#' | '$' | '@' => format!("\\{c}"),
+            '~' => " ".to_string(),
+            c => c.to_string(),
+        }
+    }
+
+    /// Translates the command after a `\`
+    fn command(&mut self) -> Result<String, String> {
+        self.nested(Self::command_name)
+    }
+
+    fn command_name(&mut self) -> Result<String, String> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            self.pos += 1;
+        }
+        if self.pos == start {
+            // Control symbols: `\{`, `\,`…
+            let Some(c) = self.peek() else {
+                return Err("trailing `\\`".to_string());
+            };
+            self.pos += 1;
+            return match c {
+                '{' | '}' | '|' | '%' | '&' | '_' => Ok(match c {
+                    '|' => "||".to_string(),
+                    '_' => "\\_".to_string(),
+                    '%' => "%".to_string(),
+                    c => format!("\\{c}"),
+                }),
+                ',' => Ok("thin".to_string()),
+                ':' | '>' => Ok("med".to_string()),
+                ';' => Ok("thick".to_string()),
+                ' ' => Ok("space".to_string()),
+                '!' => Ok(String::new()),
+                c => Err(format!("`\\{c}` has no Typst equivalent")),
+            };
+        }
+        let name: String = self.chars[start..self.pos].iter().collect();
+        if let Some(symbol) = symbol(&name) {
+            return Ok(symbol.to_string());
+        }
+        Ok(match name.as_str() {
+            "frac" | "dfrac" | "tfrac" => {
+                let numerator = self.call_argument()?;
+                let denominator = self.call_argument()?;
+                format!("frac({numerator}, {denominator})")
+            }
+            "binom" => {
+                let n = self.call_argument()?;
+                let k = self.call_argument()?;
+                format!("binom({n}, {k})")
+            }
+            "sqrt" => {
+                self.skip_spaces();
+                if self.peek() == Some('[') {
+                    self.pos += 1;
+                    self.call_depth += 1;
+                    let index = self.sequence(Some(']'), Rows::None);
+                    self.call_depth -= 1;
+                    let index = index?;
+                    let radicand = self.call_argument()?;
+                    format!("root({index}, {radicand})")
+                } else {
+                    format!("sqrt({})", self.call_argument()?)
+                }
+            }
+            "text" | "textrm" | "mbox" => string(&self.text_argument()?),
+            "operatorname" => format!("op({})", string(&self.text_argument()?)),
+            "left" | "right" | "bigl" | "bigr" | "Bigl" | "Bigr" | "big" | "Big" => {
+                // Typst sizes matching delimiters by itself
+                self.skip_spaces();
+                match self.peek() {
+                    Some('.') => {
+                        self.pos += 1;
+                        String::new()
+                    }
+                    Some(_) => self.argument()?,
+                    None => return Err(format!("`\\{name}` without a delimiter")),
+                }
+            }
+            "limits" | "nolimits" | "displaystyle" | "textstyle" => String::new(),
+            "begin" => self.environment()?,
+            _ => match wrapper(&name) {
+                Some(function) => format!("{function}({})", self.call_argument()?),
+                None => return Err(format!("`\\{name}` has no Typst equivalent")),
+            },
+        })
+    }
+
+    /// Translates `\begin{name}…\end{name}`, the `\begin` being read
+    fn environment(&mut self) -> Result<String, String> {
+        let name = self.text_argument()?;
+        let (open, rows, close) = match name.as_str() {
+            "matrix" => ("mat(delim: #none, ", Rows::Matrix, ")"),
+            "pmatrix" => ("mat(delim: \"(\", ", Rows::Matrix, ")"),
+            "bmatrix" => ("mat(delim: \"[\", ", Rows::Matrix, ")"),
+            "vmatrix" => ("mat(delim: \"|\", ", Rows::Matrix, ")"),
+            "cases" => ("cases(", Rows::Cases, ")"),
+            "aligned" | "gathered" | "split" => ("", Rows::None, ""),
+            _ => return Err(format!("the `{name}` environment has no Typst equivalent")),
+        };
+        if rows != Rows::None {
+            self.call_depth += 1;
+        }
+        let content = self.sequence(None, rows);
+        if rows != Rows::None {
+            self.call_depth -= 1;
+        }
+        let content = content?;
+        if !self.next_is("\\end{") {
+            return Err(format!("`\\begin{{{name}}}` is not closed"));
+        }
+        self.pos += 4;
+        let end = self.text_argument()?;
+        if end != name {
+            return Err(format!("`\\begin{{{name}}}` is closed by `\\end{{{end}}}`"));
+        }
+        let content = content.trim().trim_end_matches([';', ',', '\\', ' ']);
+        Ok(format!("{open}{content}{close}"))
+    }
+}
+
+/// `argument` of `^` or `_`, parenthesized unless it is a single item
+fn script(argument: &str) -> String {
+    let argument = argument.trim();
+    if !argument.is_empty() && argument.chars().all(char::is_alphanumeric) {
+        argument.to_string()
+    } else {
+        format!("({argument})")
+    }
+}
+
+/// Appends `piece` to `out`, with a space where they would otherwise run together: letters into
+/// a longer name, a name into a call, or symbols into a shorthand such as `->`
+fn push(out: &mut String, piece: &str) {
+    const SHORTHAND: &str = "<>=-!:.|+*~";
+    if let (Some(last), Some(first)) = (out.chars().last(), piece.chars().next()) {
+        if (last.is_alphanumeric() && (first.is_alphanumeric() || first == '('))
+            || (SHORTHAND.contains(last) && SHORTHAND.contains(first))
+        {
+            out.push(' ');
+        }
+    }
+    out.push_str(piece);
+}
+
+/// Commands applying a Typst function to their argument
+fn wrapper(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "mathbb" => "bb",
+        "mathbf" | "boldsymbol" | "bm" => "bold",
+        "mathit" => "italic",
+        "mathrm" => "upright",
+        "mathcal" => "cal",
+        "mathfrak" => "frak",
+        "mathsf" => "sans",
+        "mathtt" => "mono",
+        "hat" | "widehat" => "hat",
+        "tilde" | "widetilde" => "tilde",
+        "bar" => "macron",
+        "overline" => "overline",
+        "underline" => "underline",
+        "vec" | "overrightarrow" => "arrow",
+        "dot" => "dot",
+        "ddot" => "dot.double",
+        "overbrace" => "overbrace",
+        "underbrace" => "underbrace",
+        "abs" => "abs",
+        "norm" => "norm",
+        _ => return None,
+    })
+}
+
+/// Typst name of the symbol or operator a LaTeX command stands for
+fn symbol(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "alpha" => "alpha",
+        "beta" => "beta",
+        "gamma" => "gamma",
+        "delta" => "delta",
+        "epsilon" => "epsilon.alt",
+        "varepsilon" => "epsilon",
+        "zeta" => "zeta",
+        "eta" => "eta",
+        "theta" => "theta",
+        "vartheta" => "theta.alt",
+        "iota" => "iota",
+        "kappa" => "kappa",
+        "lambda" => "lambda",
+        "mu" => "mu",
+        "nu" => "nu",
+        "xi" => "xi",
+        "pi" => "pi",
+        "varpi" => "pi.alt",
+        "rho" => "rho",
+        "varrho" => "rho.alt",
+        "sigma" => "sigma",
+        "varsigma" => "sigma.alt",
+        "tau" => "tau",
+        "upsilon" => "upsilon",
+        "phi" => "phi.alt",
+        "varphi" => "phi",
+        "chi" => "chi",
+        "psi" => "psi",
+        "omega" => "omega",
+        "Gamma" => "Gamma",
+        "Delta" => "Delta",
+        "Theta" => "Theta",
+        "Lambda" => "Lambda",
+        "Xi" => "Xi",
+        "Pi" => "Pi",
+        "Sigma" => "Sigma",
+        "Upsilon" => "Upsilon",
+        "Phi" => "Phi",
+        "Psi" => "Psi",
+        "Omega" => "Omega",
+        "infty" => "infinity",
+        "partial" => "diff",
+        "nabla" => "nabla",
+        "emptyset" | "varnothing" => "emptyset",
+        "ell" => "ell",
+        "hbar" => "planck.reduce",
+        "Re" => "Re",
+        "Im" => "Im",
+        "aleph" => "aleph",
+        "prime" => "prime",
+        "cdot" => "dot.op",
+        "times" => "times",
+        "div" => "div",
+        "pm" => "plus.minus",
+        "mp" => "minus.plus",
+        "circ" => "compose",
+        "ast" => "ast",
+        "star" => "star",
+        "oplus" => "plus.circle",
+        "otimes" => "times.circle",
+        "wedge" | "land" => "and",
+        "vee" | "lor" => "or",
+        "neg" | "lnot" => "not",
+        "setminus" => "without",
+        "cup" => "union",
+        "cap" => "sect",
+        "bigcup" => "union.big",
+        "bigcap" => "sect.big",
+        "sum" => "sum",
+        "prod" => "product",
+        "coprod" => "product.co",
+        "int" => "integral",
+        "iint" => "integral.double",
+        "iiint" => "integral.triple",
+        "oint" => "integral.cont",
+        "leq" | "le" => "<=",
+        "geq" | "ge" => ">=",
+        "neq" | "ne" => "!=",
+        "ll" => "<<",
+        "gg" => ">>",
+        "approx" => "approx",
+        "equiv" => "equiv",
+        "sim" => "tilde.op",
+        "simeq" => "tilde.eq",
+        "cong" => "tilde.equiv",
+        "propto" => "prop",
+        "perp" => "perp",
+        "parallel" => "parallel",
+        "mid" => "divides",
+        "in" => "in",
+        "notin" => "in.not",
+        "ni" => "in.rev",
+        "subset" => "subset",
+        "subseteq" => "subset.eq",
+        "supset" => "supset",
+        "supseteq" => "supset.eq",
+        "forall" => "forall",
+        "exists" => "exists",
+        "nexists" => "exists.not",
+        "to" | "rightarrow" => "->",
+        "leftarrow" | "gets" => "<-",
+        "Rightarrow" | "implies" => "=>",
+        "Leftarrow" | "impliedby" => "arrow.l.double",
+        "Leftrightarrow" | "iff" => "<=>",
+        "leftrightarrow" => "<->",
+        "mapsto" => "|->",
+        "longrightarrow" => "-->",
+        "longmapsto" => "arrow.r.long.bar",
+        "hookrightarrow" => "arrow.r.hook",
+        "uparrow" => "arrow.t",
+        "downarrow" => "arrow.b",
+        "ldots" | "dots" => "dots",
+        "cdots" => "dots.c",
+        "vdots" => "dots.v",
+        "ddots" => "dots.down",
+        "langle" => "angle.l",
+        "rangle" => "angle.r",
+        "lfloor" => "floor.l",
+        "rfloor" => "floor.r",
+        "lceil" => "ceil.l",
+        "rceil" => "ceil.r",
+        "lvert" | "rvert" | "vert" => "|",
+        "lVert" | "rVert" | "Vert" => "||",
+        "quad" => "quad",
+        "qquad" => "wide",
+        "sin" => "sin",
+        "cos" => "cos",
+        "tan" => "tan",
+        "cot" => "cot",
+        "sec" => "sec",
+        "csc" => "csc",
+        "arcsin" => "arcsin",
+        "arccos" => "arccos",
+        "arctan" => "arctan",
+        "sinh" => "sinh",
+        "cosh" => "cosh",
+        "tanh" => "tanh",
+        "exp" => "exp",
+        "log" => "log",
+        "ln" => "ln",
+        "lg" => "lg",
+        "lim" => "lim",
+        "liminf" => "liminf",
+        "limsup" => "limsup",
+        "sup" => "sup",
+        "inf" => "inf",
+        "max" => "max",
+        "min" => "min",
+        "arg" => "arg",
+        "det" => "det",
+        "dim" => "dim",
+        "ker" => "ker",
+        "deg" => "deg",
+        "gcd" => "gcd",
+        "hom" => "hom",
+        "Pr" => "Pr",
+        "bmod" => "mod",
+        _ => return None,
+    })
+}
@@ -0,0 +1,28 @@
+use md_parser::{parse, to_commonmark, Options};
+
+#[test]
+fn lossy_conversions_are_reported_where_they_are() {
+    let input =
+        "Some ..underlined.. ..twice.. and ||marked|| $x$ text\n\n---\n\n%thm\nStatement\n%\n";
+    let (_, diagnostics) = to_commonmark(&parse(input), &Options::default());
+    let spans: Vec<_> = diagnostics
+        .iter()
+        .map(|diagnostic| &input[diagnostic.span.start..diagnostic.span.end])
+        .collect();
+    assert_eq!(spans, ["..", "..", "||", "$x$", "---", "%thm"]);
+}
+
+#[test]
+fn front_matter_values_are_quoted_when_needed() {
+    let input = "---\ntitle: a: b\nauthor: Jane Doe\ntag: #x\ndate: 2024-10-18\ndraft: yes\n\
+                 quote: say \"hi\" \\o/\n---\ntext\n";
+    let options = Options {
+        standalone: true,
+        ..Options::default()
+    };
+    let (markdown, _) = to_commonmark(&parse(input), &options);
+    assert!(markdown.starts_with(
+        "---\ntitle: \"a: b\"\nauthor: Jane Doe\ntag: \"#x\"\ndate: \"2024-10-18\"\n\
+         draft: \"yes\"\nquote: \"say \\\"hi\\\" \\\\o/\"\n---\n"
+    ));
+}
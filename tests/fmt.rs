@@ -0,0 +1,72 @@
+use md_parser::{format, parse, to_html, Options, ParseOptions};
+
+/// Inputs covering each construct, and the places where a block runs into text
+const INPUTS: &[&str] = &[
+    "text ```\ncode\n```\n",
+    "text ```rust\nfn main() {}\n```\nafter\n",
+    "text \\[x\\] more\n",
+    "text ```{=html}\n<b>x</b>\n```\n",
+    "text === more\n",
+    "%thm\ntext ```\ncode\n```\n\nmore ```\ncode\n```\n%\n",
+    "- item ```\ncode\n```\n",
+    "text\n# Header\n",
+    "text\n- item\n",
+    "   indented\n  continuation\n\n\n\nafter blank lines\n",
+    "#not a header\n",
+    "- a\n    - b\n      continued\n- c\n\nafter\n",
+    "*it* __bold__ *_empty_* *it __bold__* ~~s~~ ..u.. ||h|| `code` $x$ a~b \\ref{x}\n",
+    "%thm Title {#x}\nStatement\n%%proof\nDone.\n%%\n%\n",
+    "%thm\n%%\n- inside\n%%\n%\n",
+    "---\ntitle: Front matter\n---\n# A {#a}\n\n%toc\n\n\\[ x \\] {#e}\n\n---\n",
+];
+
+fn sources() -> Vec<String> {
+    let root = env!("CARGO_MANIFEST_DIR");
+    INPUTS
+        .iter()
+        .map(|input| input.to_string())
+        .chain(
+            ["test.md", "syntax.md"].map(|file| {
+                std::fs::read_to_string(format!("{root}/{file}")).expect("sample file")
+            }),
+        )
+        .collect()
+}
+
+/// HTML with line breaks and empty emphasis left out, these being all that reflowing may change
+fn meaning(input: &str) -> String {
+    let mut html = to_html(&parse(input), &Options::default())
+        .replace("<br/>", "")
+        .replace('\n', "");
+    for tag in ["em", "strong", "s", "u", "mark"] {
+        html = html.replace(&format!("<{tag}></{tag}>"), "");
+    }
+    html
+}
+
+#[test]
+fn formatting_is_idempotent() {
+    for input in sources() {
+        let once = format(&input, &ParseOptions::default());
+        let twice = format(&once, &ParseOptions::default());
+        assert_eq!(once, twice, "{input:?}");
+    }
+}
+
+#[test]
+fn formatting_keeps_the_meaning() {
+    for input in sources() {
+        let formatted = format(&input, &ParseOptions::default());
+        assert_eq!(
+            meaning(&input),
+            meaning(&formatted),
+            "{input:?} → {formatted:?}"
+        );
+    }
+}
+
+#[test]
+fn blocks_running_into_text_stay_inline() {
+    let input = "text ```\ncode\n```\n";
+    assert_eq!(format(input, &ParseOptions::default()), input);
+}
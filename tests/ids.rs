@@ -0,0 +1,40 @@
+use md_parser::{parse, Node};
+use std::collections::HashSet;
+
+/// Anchors of headers and environments, and labels of equations, in document order
+fn ids(nodes: &[Node]) -> Vec<String> {
+    let mut found = vec![];
+    for node in nodes {
+        match node {
+            Node::Header { id: Some(id), .. } | Node::Env { id: Some(id), .. } => {
+                found.push(id.to_string())
+            }
+            Node::DisplayMath {
+                label: Some(label), ..
+            } => found.push(label.name.to_string()),
+            _ => (),
+        }
+        found.extend(ids(node.children()));
+    }
+    found
+}
+
+#[test]
+fn header_slugs_do_not_collide_with_other_ids() {
+    let document = parse(
+        "# Theorem 1\n\n%thm\nA\n%\n\n# Intro\n\n\\[ x \\] {#intro}\n\n# Lemma\n\n%lemma {#lemma}\nB\n%\n",
+    );
+    let ids = ids(&document.nodes);
+    assert_eq!(
+        ids,
+        [
+            "theorem-1",
+            "theorem-1-1",
+            "intro-1",
+            "intro",
+            "lemma-1",
+            "lemma"
+        ]
+    );
+    assert_eq!(ids.iter().collect::<HashSet<_>>().len(), ids.len());
+}
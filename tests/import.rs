@@ -0,0 +1,91 @@
+use md_parser::{from_commonmark, Limits, Node, ParseOptions, Severity};
+
+fn safe() -> ParseOptions {
+    ParseOptions {
+        safe: Some(Limits::default()),
+        ..ParseOptions::default()
+    }
+}
+
+/// Deepest chain of nodes in `nodes`
+fn depth(nodes: &[Node]) -> usize {
+    nodes
+        .iter()
+        .map(|node| 1 + depth(node.children()))
+        .max()
+        .unwrap_or(0)
+}
+
+fn left_out(document: &md_parser::Document) -> bool {
+    document.diagnostics.iter().any(|diagnostic| {
+        diagnostic.severity == Severity::Error && diagnostic.message.contains("is left out")
+    })
+}
+
+#[test]
+fn deep_blockquotes_are_cut_in_safe_mode() {
+    let input = format!("{} text\n", ">".repeat(20_000));
+    let document = from_commonmark(&input, &safe());
+    assert!(left_out(&document));
+    assert!(depth(&document.nodes) <= 2 * Limits::default().max_depth);
+}
+
+#[test]
+fn deep_lists_are_cut_in_safe_mode() {
+    let input = format!("{}text\n", "- ".repeat(20_000));
+    let document = from_commonmark(&input, &safe());
+    assert!(left_out(&document));
+}
+
+#[test]
+fn deep_emphasis_is_cut_in_safe_mode() {
+    let input = format!("{}a{}\n", "*".repeat(20_000), "*".repeat(20_000));
+    let document = from_commonmark(&input, &safe());
+    assert!(left_out(&document));
+    assert!(depth(&document.nodes) <= 2 * Limits::default().max_depth);
+}
+
+#[test]
+fn unmatched_delimiters_are_linear() {
+    for delimiter in ["*a ", "_a ", "~~a ", "``a` ", "[a "] {
+        let input = delimiter.repeat(50_000);
+        let start = std::time::Instant::now();
+        from_commonmark(&input, &safe());
+        assert!(
+            start.elapsed() < std::time::Duration::from_secs(10),
+            "{delimiter:?}"
+        );
+    }
+}
+
+#[test]
+fn oversized_input_is_refused_in_safe_mode() {
+    let input = "text\n".repeat(400_000);
+    let document = from_commonmark(&input, &safe());
+    assert!(document.nodes.is_empty());
+    assert_eq!(document.diagnostics.len(), 1);
+    assert_eq!(document.diagnostics[0].severity, Severity::Error);
+}
+
+#[test]
+fn shallow_input_is_kept_in_safe_mode() {
+    let input = "> quoted *text*\n\n- a\n  - b\n";
+    let document = from_commonmark(input, &safe());
+    assert!(!left_out(&document));
+    assert_eq!(
+        depth(&document.nodes),
+        depth(&from_commonmark(input, &ParseOptions::default()).nodes)
+    );
+}
+
+#[test]
+fn differences_are_reported_where_they_are() {
+    let input = "~~~\ncode\n~~~\n\n50% off\n";
+    let document = from_commonmark(input, &ParseOptions::default());
+    let spans: Vec<_> = document
+        .diagnostics
+        .iter()
+        .map(|diagnostic| &input[diagnostic.span.start..diagnostic.span.end])
+        .collect();
+    assert_eq!(spans, ["~~~", "%"]);
+}
@@ -0,0 +1,40 @@
+use md_parser::{parse, parse_with, to_latex, Numbering, Options, ParseOptions};
+
+const INPUT: &str = "# One\n\n%thm\nA\n%\n\n## Sub\n\n# Two\n\n%thm\nB\n%\n";
+
+fn standalone() -> Options {
+    Options {
+        standalone: true,
+        ..Options::default()
+    }
+}
+
+#[test]
+fn sections_are_numbered_when_theorems_carry_their_number() {
+    let options = ParseOptions {
+        numbering: Numbering {
+            reset_depth: Some(1),
+        },
+        ..ParseOptions::default()
+    };
+    let latex = to_latex(&parse_with(INPUT, &options), &standalone());
+    assert!(latex.contains("\\newtheorem{theorem}{Theorem}[section]"));
+    assert!(latex.contains("\\section{One}"));
+    assert!(latex.contains("\\section{Two}"));
+    // Theorem numbers only carry the section's
+    assert!(latex.contains("\\subsection*{Sub}"));
+}
+
+#[test]
+fn sections_are_unnumbered_by_default() {
+    let latex = to_latex(&parse(INPUT), &standalone());
+    assert!(latex.contains("\\newtheorem{theorem}{Theorem}\n"));
+    assert!(latex.contains("\\section*{One}"));
+    let numbered = Options {
+        number_sections: true,
+        ..standalone()
+    };
+    let latex = to_latex(&parse(INPUT), &numbered);
+    assert!(latex.contains("\\section{One}"));
+    assert!(latex.contains("\\subsection{Sub}"));
+}
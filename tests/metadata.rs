@@ -0,0 +1,40 @@
+use md_parser::Metadata;
+
+#[test]
+fn front_matter_is_split_off() {
+    let (metadata, body) = Metadata::parse("---\ntitle: \"Algèbre\"\n\nlang: fr\n---\n# A\n");
+    assert_eq!(metadata.title(), Some("Algèbre"));
+    assert_eq!(metadata.lang(), Some("fr"));
+    assert_eq!(body, "# A\n");
+}
+
+#[test]
+fn crlf_front_matter_is_split_off() {
+    let (metadata, body) = Metadata::parse("---\r\ntitle: A\r\nauthor: B\r\n---\r\ntext\r\n");
+    assert_eq!(metadata.title(), Some("A"));
+    assert_eq!(metadata.author(), Some("B"));
+    assert_eq!(body, "text\r\n");
+}
+
+#[test]
+fn rules_around_text_are_not_front_matter() {
+    for input in [
+        "---\nSome text\n---\n",
+        "---\ntitle: A\nand some text\n---\n",
+        "---\nA sentence: with a colon\n---\n",
+        "---\n---\ntext\n",
+        "---\n\n---\n",
+        "---\ntitle: A\n",
+    ] {
+        let (metadata, body) = Metadata::parse(input);
+        assert!(metadata.entries.is_empty(), "{input:?}");
+        assert_eq!(body, input);
+    }
+}
+
+#[test]
+fn text_between_rules_is_kept() {
+    let document = md_parser::parse("---\nIntroduction\n---\n");
+    let html = md_parser::to_html(&document, &md_parser::Options::default());
+    assert!(html.contains("Introduction"));
+}
@@ -0,0 +1,322 @@
+use md_parser::{parse, to_pandoc, Options, SolutionMode};
+
+/// Just enough JSON to check the shape of the output
+#[derive(Debug)]
+enum Json {
+    Null,
+    Bool,
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn array(&self) -> &[Json] {
+        match self {
+            Json::Array(items) => items,
+            other => panic!("expected an array, got {other:?}"),
+        }
+    }
+
+    fn string(&self) -> &str {
+        match self {
+            Json::String(string) => string,
+            other => panic!("expected a string, got {other:?}"),
+        }
+    }
+}
+
+struct Reader<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl Reader<'_> {
+    fn value(&mut self) -> Json {
+        self.skip_whitespace();
+        match self.chars.peek().copied().expect("unexpected end of JSON") {
+            '{' => {
+                self.chars.next();
+                let mut entries = vec![];
+                loop {
+                    self.skip_whitespace();
+                    if self.eat('}') {
+                        break;
+                    }
+                    let Json::String(key) = self.value() else {
+                        panic!("expected a key");
+                    };
+                    self.skip_whitespace();
+                    assert!(self.eat(':'), "expected `:`");
+                    entries.push((key, self.value()));
+                    self.skip_whitespace();
+                    if !self.eat(',') {
+                        assert!(self.eat('}'), "expected `}}`");
+                        break;
+                    }
+                }
+                Json::Object(entries)
+            }
+            '[' => {
+                self.chars.next();
+                let mut items = vec![];
+                loop {
+                    self.skip_whitespace();
+                    if self.eat(']') {
+                        break;
+                    }
+                    items.push(self.value());
+                    self.skip_whitespace();
+                    if !self.eat(',') {
+                        assert!(self.eat(']'), "expected `]`");
+                        break;
+                    }
+                }
+                Json::Array(items)
+            }
+            '"' => {
+                self.chars.next();
+                let mut string = String::new();
+                loop {
+                    match self.chars.next().expect("unterminated string") {
+                        '"' => break,
+                        '\\' => match self.chars.next().expect("unterminated escape") {
+                            'n' => string.push('\n'),
+                            't' => string.push('\t'),
+                            'r' => string.push('\r'),
+                            'u' => {
+                                let code: String =
+                                    (0..4).filter_map(|_| self.chars.next()).collect();
+                                let code = u32::from_str_radix(&code, 16).expect("bad escape");
+                                string.push(char::from_u32(code).expect("bad escape"));
+                            }
+                            c @ ('"' | '\\' | '/') => string.push(c),
+                            c => panic!("bad escape `\\{c}`"),
+                        },
+                        c => {
+                            assert!(c >= ' ', "control character in string");
+                            string.push(c);
+                        }
+                    }
+                }
+                Json::String(string)
+            }
+            't' | 'f' | 'n' => {
+                let mut word = String::new();
+                while self.chars.peek().is_some_and(char::is_ascii_alphabetic) {
+                    word.extend(self.chars.next());
+                }
+                match word.as_str() {
+                    "true" | "false" => Json::Bool,
+                    "null" => Json::Null,
+                    _ => panic!("unexpected `{word}`"),
+                }
+            }
+            _ => {
+                let mut number = String::new();
+                while self
+                    .chars
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_digit() || "-+.eE".contains(*c))
+                {
+                    number.extend(self.chars.next());
+                }
+                Json::Number(number.parse().expect("bad number"))
+            }
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.chars.next_if_eq(&c).is_some()
+    }
+}
+
+fn read(json: &str) -> Json {
+    let mut reader = Reader {
+        chars: json.chars().peekable(),
+    };
+    let value = reader.value();
+    reader.skip_whitespace();
+    assert!(reader.chars.next().is_none(), "trailing characters");
+    value
+}
+
+fn tag(element: &Json) -> &str {
+    element.get("t").expect("element without `t`").string()
+}
+
+fn content(element: &Json) -> &Json {
+    element.get("c").expect("element without `c`")
+}
+
+/// `[id, [classes], [[key, value]]]`
+fn check_attr(attr: &Json) {
+    let [id, classes, attributes] = attr.array() else {
+        panic!("bad Attr {attr:?}");
+    };
+    id.string();
+    classes.array().iter().for_each(|class| {
+        class.string();
+    });
+    for pair in attributes.array() {
+        let [key, value] = pair.array() else {
+            panic!("bad attribute {pair:?}");
+        };
+        key.string();
+        value.string();
+    }
+}
+
+fn check_blocks(blocks: &Json) {
+    for block in blocks.array() {
+        match tag(block) {
+            "Plain" | "Para" => check_inlines(content(block)),
+            "Header" => {
+                let [Json::Number(level), attr, inlines] = content(block).array() else {
+                    panic!("bad Header {block:?}");
+                };
+                assert!((1.0..=6.0).contains(level), "bad Header level {level}");
+                check_attr(attr);
+                check_inlines(inlines);
+            }
+            "CodeBlock" => {
+                let [attr, code] = content(block).array() else {
+                    panic!("bad CodeBlock {block:?}");
+                };
+                check_attr(attr);
+                code.string();
+            }
+            "RawBlock" => {
+                let [format, text] = content(block).array() else {
+                    panic!("bad RawBlock {block:?}");
+                };
+                format.string();
+                text.string();
+            }
+            "BulletList" => content(block).array().iter().for_each(check_blocks),
+            "Div" => {
+                let [attr, blocks] = content(block).array() else {
+                    panic!("bad Div {block:?}");
+                };
+                check_attr(attr);
+                check_blocks(blocks);
+            }
+            "HorizontalRule" => assert!(block.get("c").is_none()),
+            other => panic!("`{other}` is not a block"),
+        }
+    }
+}
+
+fn check_inlines(inlines: &Json) {
+    for inline in inlines.array() {
+        match tag(inline) {
+            "Str" => {
+                content(inline).string();
+            }
+            "Space" | "LineBreak" => assert!(inline.get("c").is_none()),
+            "Emph" | "Strong" | "Strikeout" => check_inlines(content(inline)),
+            "Code" => {
+                let [attr, code] = content(inline).array() else {
+                    panic!("bad Code {inline:?}");
+                };
+                check_attr(attr);
+                code.string();
+            }
+            "Math" => {
+                let [kind, math] = content(inline).array() else {
+                    panic!("bad Math {inline:?}");
+                };
+                assert!(matches!(tag(kind), "InlineMath" | "DisplayMath"));
+                math.string();
+            }
+            "Span" => {
+                let [attr, inlines] = content(inline).array() else {
+                    panic!("bad Span {inline:?}");
+                };
+                check_attr(attr);
+                check_inlines(inlines);
+            }
+            "Link" => {
+                let [attr, inlines, target] = content(inline).array() else {
+                    panic!("bad Link {inline:?}");
+                };
+                check_attr(attr);
+                check_inlines(inlines);
+                let [url, title] = target.array() else {
+                    panic!("bad Link target {target:?}");
+                };
+                url.string();
+                title.string();
+            }
+            other => panic!("`{other}` is not an inline"),
+        }
+    }
+}
+
+/// Renders `input` with every solution mode and checks that each output is a valid Pandoc
+/// document, returning the one with solutions left inline
+fn check(input: &str) -> Json {
+    let document = parse(input);
+    let mut inline = None;
+    for solutions in [
+        SolutionMode::Inline,
+        SolutionMode::Collect,
+        SolutionMode::Strip,
+    ] {
+        let options = Options {
+            solutions,
+            ..Options::default()
+        };
+        let json = read(&to_pandoc(&document, &options));
+        assert!(json.get("pandoc-api-version").is_some());
+        assert!(matches!(json.get("meta"), Some(Json::Object(_))));
+        check_blocks(json.get("blocks").expect("no blocks"));
+        inline.get_or_insert(json);
+    }
+    inline.unwrap()
+}
+
+#[test]
+fn inline_content_is_wrapped_at_top_level() {
+    for input in ["\n# a\n", "text\n# a\n", "\\[a\\]\n", "a\nb\n"] {
+        check(input);
+    }
+}
+
+#[test]
+fn environments_are_divs() {
+    let json = check("%thm Title {#t}\nStatement $x$.\n%\n\n%sol\nAnswer\n%\n\nSee \\ref{t}.\n");
+    let blocks = json.get("blocks").unwrap().array();
+    assert_eq!(tag(&blocks[0]), "Div");
+    let [attr, _] = content(&blocks[0]).array() else {
+        panic!()
+    };
+    let [id, classes, _] = attr.array() else {
+        panic!()
+    };
+    assert_eq!(id.string(), "t");
+    let classes: Vec<_> = classes.array().iter().map(Json::string).collect();
+    assert_eq!(classes, ["environment", "theorem"]);
+}
+
+#[test]
+fn every_node_kind() {
+    check(
+        "---\ntitle: A \"quoted\" title\n---\n# Header {#h}\n\n%toc\n\n**bold** _it_ ~~s~~ ..u.. \
+         ||m|| `code` ~ \\ref{h}\n\n- a\n  - b\n- c\n\n```rust\nfn main() {}\n```\n\n```{=html}\n\
+         <b>x</b>\n```\n\n\\[ x \\] {#e}\n\n---\n\n%proof\nDone.\n%\n",
+    );
+}
@@ -0,0 +1,21 @@
+use md_parser::{parse, to_html, Options};
+
+fn html(input: &str) -> String {
+    to_html(&parse(input), &Options::default())
+}
+
+#[test]
+fn text_before_a_block_that_cancels_a_paragraph_is_kept() {
+    for (input, block) in [
+        ("text\n# Header\n", "<h1"),
+        ("text\n- item\n", "<li>"),
+        ("text\n```\ncode\n```\n", "<pre>"),
+        ("text\n\\[x\\]\n", "math-display"),
+        ("text\n%thm\nstatement\n%\n", "class=\"environment"),
+    ] {
+        let html = html(input);
+        assert!(html.contains("text"), "{input:?} lost its text: {html}");
+        assert!(html.contains(block), "{input:?} lost its block: {html}");
+        assert!(html.find("text") < html.find(block), "{input:?}: {html}");
+    }
+}
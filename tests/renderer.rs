@@ -0,0 +1,49 @@
+use md_parser::{parse, HtmlRenderer, Node, Options, Renderer};
+
+/// Renders environments as asides and leaves everything else to the default hooks
+struct AsideRenderer {
+    options: Options,
+}
+
+impl Renderer for AsideRenderer {
+    fn options(&self) -> &Options {
+        &self.options
+    }
+
+    fn environment(&mut self, node: &Node) -> String {
+        match node {
+            Node::Env { children, .. } => format!("<aside>{}</aside>", self.nodes(children)),
+            _ => String::new(),
+        }
+    }
+}
+
+#[test]
+fn overriding_one_hook_keeps_the_html_of_the_others() {
+    let before = "# Title\n\nSome *text*, `code` and $x$.\n\n- item\n\n";
+    let input = format!("{before}%thm\nInside **bold**\n%\n");
+    let document = parse(&input);
+
+    let html = HtmlRenderer::new(Options::default()).document(&document);
+    let custom = AsideRenderer {
+        options: Options::default(),
+    }
+    .document(&document);
+
+    let env = document
+        .nodes
+        .iter()
+        .position(|node| matches!(node, Node::Env { .. }))
+        .expect("an environment");
+    let Node::Env { children, .. } = &document.nodes[env] else {
+        unreachable!()
+    };
+    let mut html_renderer = HtmlRenderer::new(Options::default());
+    let inside = html_renderer.nodes(children);
+    let head = html_renderer.nodes(&document.nodes[..env]);
+    let tail = html_renderer.nodes(&document.nodes[env + 1..]);
+    assert!(inside.contains("<strong>bold</strong>"), "{inside}");
+    assert_eq!(custom, format!("{head}<aside>{inside}</aside>{tail}"));
+    assert!(html.starts_with(&head), "{html}");
+    assert_ne!(custom, html);
+}
@@ -0,0 +1,99 @@
+use md_parser::{
+    from_commonmark, parse_with, to_html, Limits, Options, ParseOptions, SolutionMode,
+};
+
+fn safe() -> ParseOptions {
+    ParseOptions {
+        safe: Some(Limits::default()),
+        ..ParseOptions::default()
+    }
+}
+
+/// Parses and renders `input` in safe mode, which must not panic whatever the input
+fn render_safely(input: &str) {
+    let document = parse_with(input, &safe());
+    for solutions in [SolutionMode::Inline, SolutionMode::Collect] {
+        let options = Options {
+            safe: true,
+            solutions,
+            ..Options::default()
+        };
+        to_html(&document, &options);
+    }
+}
+
+#[test]
+fn untrusted_input_is_parsed_and_rendered_safely() {
+    let options = Options {
+        safe: true,
+        ..Options::default()
+    };
+    let input = format!(
+        "Hello\n\n```{{=html}}\n<script>alert(1)</script>\n```\n\n%include /etc/passwd\n\n\
+         [x](javascript:alert(1))\n\n{}deep\n",
+        "**".repeat(100)
+    );
+    let document = parse_with(&input, &safe());
+    assert!(document
+        .diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.message.contains("cannot include")));
+    let html = to_html(&document, &options);
+    assert!(!html.contains("<script>"), "{html}");
+    assert!(!html.contains("root:"), "{html}");
+    assert!(!html.contains("<a "), "{html}");
+    assert!(html.contains("Hello"), "{html}");
+
+    // Links and images only come from imports, whose `javascript:` and `data:` URLs are dropped
+    let input = "[a](javascript:alert(1)) [b](JavaScript:x) ![c](data:image/png;base64,x) \
+                 [d](https://example.com) <b onclick=\"x\">e</b>\n";
+    let html = to_html(&from_commonmark(input, &safe()), &options);
+    assert!(!html.to_lowercase().contains("javascript:"), "{html}");
+    assert!(!html.contains("data:"), "{html}");
+    assert!(!html.contains("<b "), "{html}");
+    assert!(
+        html.contains("<a href=\"https://example.com\">d</a>"),
+        "{html}"
+    );
+}
+
+#[test]
+fn shallow_indent_after_list_item() {
+    render_safely("- a\n b\n");
+}
+
+#[test]
+fn long_indent() {
+    render_safely(&format!("{}text\n", " ".repeat(300)));
+    render_safely(&format!("- a\n{}- b\n", " ".repeat(300)));
+}
+
+#[test]
+fn long_header_marker() {
+    render_safely(&format!("{} title\n", "#".repeat(256)));
+    render_safely(&format!("{} title\n\ntext\n", "#".repeat(255)));
+}
+
+#[test]
+fn long_environment_marker() {
+    let marks = "%".repeat(300);
+    let input = format!("{marks}thm\ntext\n{marks}\n");
+    let document = parse_with(&input, &safe());
+    // Both lines saturate at the same depth, so they still match
+    assert!(document.diagnostics.is_empty());
+    render_safely(&format!("{marks}thm\ntext\n{}\n", "%".repeat(256)));
+}
+
+#[test]
+fn mismatched_closers_are_linear() {
+    // Each `%a` used to scan to the end of the input looking for a `%`, which took minutes for
+    // inputs within `max_size`
+    let input = "%a\n%%\n".repeat(100_000);
+    let start = std::time::Instant::now();
+    let document = parse_with(&input, &safe());
+    assert!(start.elapsed() < std::time::Duration::from_secs(10));
+    assert_eq!(document.diagnostics.len(), 100_000);
+    assert!(document.diagnostics[0]
+        .message
+        .contains("`%%` closes `%a`, which expects `%`"));
+}
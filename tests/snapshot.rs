@@ -0,0 +1,22 @@
+use md_parser::{parse, to_html, Options};
+
+/// `test.md` renders byte for byte as `md-parser test.md` did when the snapshot was taken
+///
+/// Refactors must leave the output alone. A change meant to alter it regenerates the snapshot
+/// with `UPDATE_SNAPSHOTS=1 cargo test --test snapshot`.
+#[test]
+fn test_md_to_html() {
+    let root = env!("CARGO_MANIFEST_DIR");
+    let input = std::fs::read_to_string(format!("{root}/test.md")).unwrap();
+    let options = Options {
+        standalone: true,
+        ..Options::default()
+    };
+    let html = to_html(&parse(&input), &options);
+    let path = format!("{root}/tests/snapshots/test.html");
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::write(&path, &html).unwrap();
+    }
+    let expected = std::fs::read_to_string(&path).unwrap();
+    assert!(html == expected, "test.md no longer renders as {path}");
+}
@@ -0,0 +1,39 @@
+use md_parser::{parse, to_typst, Options};
+
+#[test]
+fn line_starts_are_escaped() {
+    let (typst, _) = to_typst(
+        &parse("1. one\n\n12. twelve\n\n+ b\n\n= c\n\nversion 1.2\n"),
+        &Options::default(),
+    );
+    assert!(typst.contains("1\\. one"));
+    assert!(typst.contains("12\\. twelve"));
+    assert!(typst.contains("\\+ b"));
+    assert!(typst.contains("\\= c"));
+    assert!(typst.contains("version 1.2"));
+}
+
+#[test]
+fn untranslatable_math_is_reported_where_it_is() {
+    let input = "Some $\\unknown x$ here\n\n\\[\n\\weird\n\\]\n";
+    let (_, diagnostics) = to_typst(&parse(input), &Options::default());
+    let spans: Vec<_> = diagnostics
+        .iter()
+        .map(|diagnostic| &input[diagnostic.span.start..diagnostic.span.end])
+        .collect();
+    assert_eq!(spans, ["$\\unknown x$", "\\[\n\\weird\n\\]"]);
+}
+
+#[test]
+fn deeply_nested_math_is_reported_instead_of_overflowing() {
+    for open in ["{", "\\sqrt", "\\frac"] {
+        let input = format!("${}x$", open.repeat(200_000));
+        let (typst, diagnostics) = to_typst(&parse(&input), &Options::default());
+        assert_eq!(diagnostics.len(), 1, "{open}");
+        assert_eq!(
+            diagnostics[0].span.end - diagnostics[0].span.start,
+            input.len()
+        );
+        assert!(typst.len() >= input.len() - 2, "{open}");
+    }
+}